@@ -2,23 +2,38 @@ use crate::anki_connect::client::{AnkiClient, AnkiError};
 use crate::anki_connect::rpc::add_note::{AddNoteInput, AddNoteNoteMessage};
 use crate::anki_connect::rpc::create_deck::CreateDeckInput;
 use crate::anki_connect::rpc::create_model::{CreateModelCardTemplateMessage, CreateModelInput};
+use crate::anki_connect::rpc::model_field_add::ModelFieldAddInput;
+use crate::anki_connect::rpc::model_field_names::ModelFieldNamesInput;
+use crate::anki_connect::rpc::model_field_remove::ModelFieldRemoveInput;
+use crate::anki_connect::rpc::model_template_add::ModelTemplateAddInput;
+use crate::anki_connect::rpc::model_template_remove::ModelTemplateRemoveInput;
+use crate::anki_connect::rpc::model_templates::ModelTemplatesInput;
 use crate::anki_connect::rpc::update_model_styling::{
     UpdateModelModelMessage, UpdateModelStylingInput,
 };
 use crate::anki_connect::rpc::update_model_templates::{
     UpdateModelCardTemplateMessage, UpdateModelContentMessage, UpdateModelTemplatesInput,
 };
+use crate::configuration::{
+    ConfigurationDeckOptions, ConfigurationFieldOptions, ConfigurationTemplateOptions,
+};
+use crate::furigana::FuriganaDictionary;
 use crate::kanji::ApiKanjiMessage;
+use crate::resources::{builtin_contents, Resource};
+use crate::template::TemplateError;
 use crate::vocabulary::ApiVocabularyMessage;
-use crate::ConfigurationDeckOptions;
 use std::collections::HashMap;
+use thiserror::Error;
 
 impl AnkiClient<'_> {
-    pub async fn update_model_styling(&self, model_name: &str) -> Result<(), AnkiError> {
+    pub async fn update_model_styling(
+        &self,
+        model: &ConfigurationDeckOptions,
+    ) -> Result<(), AnkiError> {
         let request = UpdateModelStylingInput {
             model: UpdateModelModelMessage {
-                name: model_name.to_owned(),
-                css: tokio::fs::read_to_string("res/anki.css").await?,
+                name: model.model_name.to_owned(),
+                css: model.css.read().await?,
             },
         };
         match self.send(request).await {
@@ -28,87 +43,208 @@ impl AnkiClient<'_> {
         }
     }
 
+    /// Synchronize the card templates of an existing model with the configured ones.
+    ///
+    /// Templates missing from the model are added, and existing templates are updated. Templates that are not in the
+    /// configuration are only removed when `prune` is set, because removing a template deletes its cards.
     pub async fn update_model_templates(
         &self,
         model: &ConfigurationDeckOptions,
+        prune: bool,
     ) -> Result<(), AnkiError> {
+        let existing = self
+            .send(ModelTemplatesInput {
+                model_name: model.model_name.to_owned(),
+            })
+            .await?;
+
+        let mut templates = HashMap::new();
+        for template in &model.templates {
+            let front = template.front.read().await?;
+            let back = template.back.read().await?;
+            if existing.contains_key(&template.name) {
+                templates.insert(
+                    template.name.to_owned(),
+                    UpdateModelCardTemplateMessage { front, back },
+                );
+                continue;
+            }
+            tracing::info!(
+                "adding card template '{}' to model '{}'",
+                template.name,
+                model.model_name
+            );
+            let request = ModelTemplateAddInput {
+                model_name: model.model_name.to_owned(),
+                template: CreateModelCardTemplateMessage {
+                    name: template.name.to_owned(),
+                    front,
+                    back,
+                },
+            };
+            match self.send(request).await {
+                Ok(_) | Err(AnkiError::EmptyResponse) => {}
+                Err(e) => return Err(e),
+            }
+        }
+
         let request = UpdateModelTemplatesInput {
             model: UpdateModelContentMessage {
                 name: model.model_name.to_owned(),
-                templates: HashMap::from([(
-                    model.model_template_name.to_owned(),
-                    UpdateModelCardTemplateMessage {
-                        front: tokio::fs::read_to_string(model.model_template_front.clone())
-                            .await?,
-                        back: tokio::fs::read_to_string(model.model_template_back.clone()).await?,
-                    },
-                )]),
+                templates,
             },
         };
         match self.send(request).await {
-            Ok(_) => Ok(()),
-            Err(AnkiError::EmptyResponse) => Ok(()),
-            Err(e) => Err(e),
+            Ok(_) | Err(AnkiError::EmptyResponse) => {}
+            Err(e) => return Err(e),
         }
+
+        let obsolete = existing
+            .keys()
+            .filter(|name| !model.templates.iter().any(|t| &t.name == *name));
+        for name in obsolete {
+            if !prune {
+                tracing::warn!(
+                    "card template '{}' of model '{}' is not configured, pass --prune to remove it",
+                    name,
+                    model.model_name
+                );
+                continue;
+            }
+            tracing::info!(
+                "removing card template '{}' from model '{}'",
+                name,
+                model.model_name
+            );
+            let request = ModelTemplateRemoveInput {
+                model_name: model.model_name.to_owned(),
+                template_name: name.to_owned(),
+            };
+            match self.send(request).await {
+                Ok(_) | Err(AnkiError::EmptyResponse) => {}
+                Err(e) => return Err(e),
+            }
+        }
+        Ok(())
     }
 
-    pub async fn create_kanji_model(
-        &self,
-        model_name: &str,
-        model_template_name: &str,
-    ) -> Result<i64, AnkiError> {
-        let request = CreateModelInput {
+    pub async fn model_field_names(&self, model_name: &str) -> Result<Vec<String>, AnkiError> {
+        self.send(ModelFieldNamesInput {
             model_name: model_name.to_owned(),
-            css: tokio::fs::read_to_string("res/anki.css").await?,
-            is_cloze: false,
-            in_order_fields: vec![
-                "kanji".to_owned(),
-                "primary-meaning".to_owned(),
-                "primary-meaning-mnemonic".to_owned(),
-                "secondary-meanings".to_owned(),
-                "primary-reading-mnemonic".to_owned(),
-                "readings".to_owned(),
-                "reference-url".to_owned(),
-            ],
-            card_templates: vec![CreateModelCardTemplateMessage {
-                name: model_template_name.to_owned(),
-                front: tokio::fs::read_to_string("res/kanji-card-front.html").await?,
-                back: tokio::fs::read_to_string("res/kanji-card-back.html").await?,
-            }],
-        };
-        Ok(self.send(request).await?.id)
+        })
+        .await
     }
 
-    pub async fn create_vocabulary_model(
+    /// Compare the fields generated for notes against the fields of the model in Anki.
+    ///
+    /// Anki Connect silently drops fields that a model does not have, so generating such fields is an error. Fields of
+    /// the model that are not generated are left untouched, which only warrants a warning.
+    #[tracing::instrument(skip(self, generated), err)]
+    pub async fn check_model_fields(
         &self,
         model_name: &str,
-        model_template_name: &str,
-    ) -> Result<i64, AnkiError> {
+        generated: &[String],
+    ) -> Result<(), AnkiError> {
+        let existing = self.model_field_names(model_name).await?;
+        let missing = generated
+            .iter()
+            .filter(|name| !existing.contains(name))
+            .cloned()
+            .collect::<Vec<_>>();
+        let unexpected = existing
+            .iter()
+            .filter(|name| !generated.contains(name))
+            .cloned()
+            .collect::<Vec<_>>();
+        if !unexpected.is_empty() {
+            tracing::warn!(
+                "fields of model '{}' that are not generated and will be left as they are: {}",
+                model_name,
+                unexpected.join(", ")
+            );
+        }
+        if !missing.is_empty() {
+            return Err(AnkiError::FieldMismatch {
+                model: model_name.to_owned(),
+                missing,
+                unexpected,
+            });
+        }
+        Ok(())
+    }
+
+    /// Add the configured fields that are missing from an existing model.
+    ///
+    /// Fields of the model that are not in the configuration are only removed when `prune` is set, because removing a
+    /// field deletes its contents from every note.
+    pub async fn update_model_fields(
+        &self,
+        model: &ConfigurationDeckOptions,
+        prune: bool,
+    ) -> Result<(), AnkiError> {
+        let mut existing = self.model_field_names(&model.model_name).await?;
+        let configured = model.field_names();
+
+        for (index, name) in configured.iter().enumerate() {
+            if existing.contains(name) {
+                continue;
+            }
+            tracing::info!("adding field '{}' to model '{}'", name, model.model_name);
+            let index = index.min(existing.len());
+            let request = ModelFieldAddInput {
+                model_name: model.model_name.to_owned(),
+                field_name: name.to_owned(),
+                index,
+            };
+            match self.send(request).await {
+                Ok(_) | Err(AnkiError::EmptyResponse) => {}
+                Err(e) => return Err(e),
+            }
+            existing.insert(index, name.to_owned());
+        }
+
+        let obsolete = existing.iter().filter(|name| !configured.contains(name));
+        for name in obsolete {
+            if !prune {
+                tracing::warn!(
+                    "field '{}' of model '{}' is not configured, pass --prune to remove it",
+                    name,
+                    model.model_name
+                );
+                continue;
+            }
+            tracing::info!(
+                "removing field '{}' from model '{}'",
+                name,
+                model.model_name
+            );
+            let request = ModelFieldRemoveInput {
+                model_name: model.model_name.to_owned(),
+                field_name: name.to_owned(),
+            };
+            match self.send(request).await {
+                Ok(_) | Err(AnkiError::EmptyResponse) => {}
+                Err(e) => return Err(e),
+            }
+        }
+        Ok(())
+    }
+
+    pub async fn create_model(&self, model: &ConfigurationDeckOptions) -> Result<i64, AnkiError> {
+        let mut card_templates = Vec::new();
+        for template in &model.templates {
+            card_templates.push(CreateModelCardTemplateMessage {
+                name: template.name.to_owned(),
+                front: template.front.read().await?,
+                back: template.back.read().await?,
+            });
+        }
         let request = CreateModelInput {
-            model_name: model_name.to_owned(),
-            css: tokio::fs::read_to_string("res/anki.css").await?,
+            model_name: model.model_name.to_owned(),
+            css: model.css.read().await?,
             is_cloze: false,
-            in_order_fields: vec![
-                "vocabulary".to_owned(),
-                "primary-meaning".to_owned(),
-                "primary-meaning-mnemonic".to_owned(),
-                "secondary-meanings".to_owned(),
-                "primary-reading".to_owned(),
-                "primary-reading-mnemonic".to_owned(),
-                "secondary-readings".to_owned(),
-                "context-sentence-1-en".to_owned(),
-                "context-sentence-1-ja".to_owned(),
-                "context-sentence-2-en".to_owned(),
-                "context-sentence-2-ja".to_owned(),
-                "context-sentence-3-en".to_owned(),
-                "context-sentence-3-ja".to_owned(),
-                "reference-url".to_owned(),
-            ],
-            card_templates: vec![CreateModelCardTemplateMessage {
-                name: model_template_name.to_owned(),
-                front: tokio::fs::read_to_string("res/vocabulary-card-front.html").await?,
-                back: tokio::fs::read_to_string("res/vocabulary-card-back.html").await?,
-            }],
+            in_order_fields: model.field_names(),
+            card_templates,
         };
         Ok(self.send(request).await?.id)
     }
@@ -121,161 +257,376 @@ impl AnkiClient<'_> {
     }
 }
 
-impl ApiKanjiMessage {
-    pub fn into_anki_input(self, model_name: &str, deck_name: &str) -> AddNoteInput {
-        let attributes = {
-            let mut attr = HashMap::new();
-            // SAFETY: All kanji have a characters field. Only radical items may not have a characters field.
-            attr.insert(
-                "kanji".to_owned(),
-                self.subject
-                    .characters
-                    .expect("kanji must have a characters field")
-                    .to_owned(),
-            );
+/// The card templates of the kanji note type when none are configured, using the built-in HTML files.
+pub fn default_kanji_templates() -> Vec<ConfigurationTemplateOptions> {
+    vec![ConfigurationTemplateOptions {
+        name: "Default type for 'Japanese Kanji'".to_owned(),
+        front: Resource::builtin("res/kanji-card-front.html"),
+        back: Resource::builtin("res/kanji-card-back.html"),
+    }]
+}
 
-            let primary_meaning = self
-                .subject
-                .meanings
-                .iter()
-                .find(|m| m.primary)
-                .expect("kanji must have a meaning marked primary");
-            attr.insert(
-                "primary-meaning".to_owned(),
-                primary_meaning.meaning.clone(),
-            );
+/// The card templates of the vocabulary note type when none are configured, using the built-in HTML files.
+pub fn default_vocabulary_templates(context_sentences: usize) -> Vec<ConfigurationTemplateOptions> {
+    vec![ConfigurationTemplateOptions {
+        name: "Default type for 'Japanese Vocabulary'".to_owned(),
+        front: Resource::builtin("res/vocabulary-card-front.html"),
+        back: vocabulary_back_template(context_sentences),
+    }]
+}
 
-            attr.insert(
-                "primary-meaning-mnemonic".to_owned(),
-                self.subject.meaning_mnemonic.clone(),
-            );
-            attr.insert(
-                "primary-reading-mnemonic".to_owned(),
-                self.reading_mnemonic.clone(),
-            );
-            attr.insert(
-                "reference-url".to_owned(),
-                self.subject.document_url.clone(),
-            );
+/// The built-in vocabulary back template, whose list of context sentences is generated for the given number of
+/// sentences when it differs from the built-in three.
+fn vocabulary_back_template(context_sentences: usize) -> Resource {
+    const LIST_START: &str = "<dl class=\"context__list\">\n";
+    const LIST_END: &str = "      </dl>";
+    let template = Resource::builtin("res/vocabulary-card-back.html");
+    if context_sentences == DEFAULT_CONTEXT_SENTENCES {
+        return template;
+    }
+    let Some((head, tail)) = builtin_contents("res/vocabulary-card-back.html")
+        .and_then(|contents| contents.split_once(LIST_START))
+        .and_then(|(head, rest)| Some((head, rest.split_once(LIST_END)?.1)))
+    else {
+        return template;
+    };
+    let entries = (1..=context_sentences)
+        .map(|n| {
+            format!(
+                concat!(
+                    "        <dt>\n",
+                    "          {{{{furigana:context-sentence-{0}-ja-furigana}}}}\n",
+                    "        </dt>\n",
+                    "        <dd>\n",
+                    "          {{{{context-sentence-{0}-en}}}}\n",
+                    "        </dd>\n",
+                ),
+                n
+            )
+        })
+        .collect::<String>();
+    Resource::Generated([head, LIST_START, &entries, LIST_END, tail].concat())
+}
+
+/// The fields of the kanji note type when none are configured
+pub fn default_kanji_fields() -> Vec<ConfigurationFieldOptions> {
+    vec![
+        ConfigurationFieldOptions::new("kanji", "characters"),
+        ConfigurationFieldOptions::new("primary-meaning", "meanings[primary].meaning").required(),
+        ConfigurationFieldOptions::new("primary-meaning-mnemonic", "meaning_mnemonic"),
+        ConfigurationFieldOptions::new("secondary-meanings", "meanings[!primary].meaning"),
+        ConfigurationFieldOptions::new("primary-reading", "readings[primary].reading").required(),
+        ConfigurationFieldOptions::new("primary-reading-mnemonic", "reading_mnemonic"),
+        ConfigurationFieldOptions::from_template(
+            "onyomi",
+            "{{ reading_list(readings, 'onyomi') }}",
+        ),
+        ConfigurationFieldOptions::from_template(
+            "kunyomi",
+            "{{ reading_list(readings, 'kunyomi') }}",
+        ),
+        ConfigurationFieldOptions::from_template(
+            "nanori",
+            "{{ reading_list(readings, 'nanori') }}",
+        ),
+        ConfigurationFieldOptions::new("reference-url", "document_url"),
+    ]
+}
+
+/// The number of context sentences in the built-in vocabulary note type when `context_sentences` is not configured.
+pub const DEFAULT_CONTEXT_SENTENCES: usize = 3;
+
+/// The fields of the vocabulary note type when none are configured
+pub fn default_vocabulary_fields(context_sentences: usize) -> Vec<ConfigurationFieldOptions> {
+    let mut fields = vec![
+        ConfigurationFieldOptions::new("vocabulary", "characters"),
+        ConfigurationFieldOptions::new("vocabulary-furigana", "with_furigana.characters"),
+        ConfigurationFieldOptions::new("primary-meaning", "meanings[primary].meaning").required(),
+        ConfigurationFieldOptions::new("primary-meaning-mnemonic", "meaning_mnemonic"),
+        ConfigurationFieldOptions::new("secondary-meanings", "meanings[!primary].meaning"),
+        ConfigurationFieldOptions::new("primary-reading", "readings[primary].reading").required(),
+        ConfigurationFieldOptions::new("primary-reading-mnemonic", "reading_mnemonic"),
+        ConfigurationFieldOptions::new("secondary-readings", "readings[!primary].reading"),
+    ];
+    for i in 0..context_sentences {
+        // Field names are numbered from one, while the sentences are indexed from zero.
+        let n = i + 1;
+        fields.extend([
+            ConfigurationFieldOptions::new(
+                &format!("context-sentence-{}-en", n),
+                &format!("context_sentences[{}].en", i),
+            ),
+            ConfigurationFieldOptions::new(
+                &format!("context-sentence-{}-ja", n),
+                &format!("context_sentences[{}].ja", i),
+            ),
+            ConfigurationFieldOptions::new(
+                &format!("context-sentence-{}-ja-furigana", n),
+                &format!("with_furigana.context_sentences[{}]", i),
+            ),
+        ]);
+    }
+    fields.push(ConfigurationFieldOptions::new(
+        "reference-url",
+        "document_url",
+    ));
+    fields
+}
+
+#[derive(Debug, Error)]
+pub enum ConversionError {
+    #[error("subject {id} ({slug}) has no value for required field '{field}'")]
+    MissingField {
+        id: i64,
+        slug: String,
+        field: String,
+    },
+    #[error("subject {id} ({slug}) failed to render field '{field}': {source}")]
+    Template {
+        id: i64,
+        slug: String,
+        field: String,
+        source: TemplateError,
+    },
+}
 
-            let alternative_meanings = self
-                .subject
-                .meanings
-                .iter()
-                .filter(|m| !m.primary)
-                .map(|m| m.meaning.clone())
-                .collect::<Vec<_>>()
-                .join(", ");
-            attr.insert("secondary-meanings".to_owned(), alternative_meanings);
-
-            let readings = self
-                .readings
-                .iter()
-                .map(|r| r.reading.clone())
-                .collect::<Vec<_>>()
-                .join(", ");
-            attr.insert("readings".to_owned(), readings);
-
-            attr
+impl ApiKanjiMessage {
+    pub fn into_anki_input(
+        self,
+        deck: &ConfigurationDeckOptions,
+    ) -> Result<AddNoteInput, ConversionError> {
+        into_anki_input(to_json(&self), deck, "WaniKani Kanji")
+    }
+}
+
+impl ApiVocabularyMessage {
+    /// Build the note for this vocabulary. On top of the subject's own properties, fields can refer to the
+    /// `with_furigana.characters` and `with_furigana.context_sentences` properties for furigana markup.
+    pub fn into_anki_input(
+        self,
+        deck: &ConfigurationDeckOptions,
+        furigana: &FuriganaDictionary,
+    ) -> Result<AddNoteInput, ConversionError> {
+        let characters = match (
+            &self.subject.characters,
+            self.readings.iter().find(|r| r.primary),
+        ) {
+            (Some(characters), Some(reading)) => {
+                furigana.annotate_word(characters, &reading.reading)
+            }
+            (characters, _) => characters.clone().unwrap_or_default(),
         };
-        AddNoteInput {
-            note: AddNoteNoteMessage {
-                deck_name: deck_name.to_owned(),
-                model_name: model_name.to_owned(),
-                tags: vec!["WaniKani Kanji".to_owned()],
-                audio: vec![],
-                picture: vec![],
-                video: vec![],
-                fields: attributes,
-            },
+        let context_sentences = self
+            .context_sentences
+            .iter()
+            .map(|sentence| furigana.annotate_sentence(&sentence.ja))
+            .collect::<Vec<_>>();
+        let mut subject = to_json(&self);
+        subject["with_furigana"] = serde_json::json!({
+            "characters": characters,
+            "context_sentences": context_sentences,
+        });
+        into_anki_input(subject, deck, "WaniKani Vocabulary")
+    }
+}
+
+fn to_json<T: serde::Serialize>(subject: &T) -> serde_json::Value {
+    // SAFETY: Subjects are plain data structures that were deserialized from JSON in the first place.
+    serde_json::to_value(subject).expect("subject must be serializable to json")
+}
+
+/// Build the note for a subject by evaluating each configured field against it.
+///
+/// The first field identifies the note, so it is always required to have a value.
+fn into_anki_input(
+    subject: serde_json::Value,
+    deck: &ConfigurationDeckOptions,
+    tag: &str,
+) -> Result<AddNoteInput, ConversionError> {
+    let id = subject["id"].as_i64().unwrap_or_default();
+    let slug = subject["slug"].as_str().unwrap_or_default().to_owned();
+    let mut attributes = HashMap::new();
+    for (i, field) in deck.fields.iter().enumerate() {
+        let value = field
+            .render(&subject)
+            .map_err(|source| ConversionError::Template {
+                id,
+                slug: slug.clone(),
+                field: field.name.clone(),
+                source,
+            })?;
+        if value.is_empty() && (i == 0 || field.required) {
+            return Err(ConversionError::MissingField {
+                id,
+                slug,
+                field: field.name.clone(),
+            });
         }
+        attributes.insert(field.name.clone(), value);
     }
+    Ok(AddNoteInput {
+        note: AddNoteNoteMessage {
+            deck_name: deck.deck_name.to_owned(),
+            model_name: deck.model_name.to_owned(),
+            tags: vec![tag.to_owned()],
+            audio: vec![],
+            picture: vec![],
+            video: vec![],
+            fields: attributes,
+        },
+    })
 }
 
-impl ApiVocabularyMessage {
-    pub fn into_anki_input(self, model_name: &str, deck_name: &str) -> AddNoteInput {
-        let attributes = {
-            let mut attr = HashMap::new();
-            // SAFETY: All kanji have a characters field. Only radical items may not have a characters field.
-            attr.insert(
-                "vocabulary".to_owned(),
-                self.subject
-                    .characters
-                    .expect("vocabulary must have a characters field")
-                    .to_owned(),
-            );
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::query::ApiSubjectMessage;
+    use serde_json::{json, Value};
 
-            let primary_meaning = self
-                .subject
-                .meanings
-                .iter()
-                .find(|m| m.primary)
-                .expect("vocabulary must have a meaning marked primary");
-            attr.insert(
-                "primary-meaning".to_owned(),
-                primary_meaning.meaning.clone(),
-            );
+    /// A vocabulary with the given number of context sentences, numbered from one.
+    fn vocabulary(sentences: usize) -> Value {
+        let sentences = (1..=sentences)
+            .map(|n| json!({ "en": format!("en {}", n), "ja": format!("ja {}", n) }))
+            .collect::<Vec<_>>();
+        let furigana = (1..=sentences.len())
+            .map(|n| format!("furigana {}", n))
+            .collect::<Vec<_>>();
+        json!({
+            "context_sentences": sentences,
+            "with_furigana": { "context_sentences": furigana },
+        })
+    }
 
-            attr.insert(
-                "primary-meaning-mnemonic".to_owned(),
-                self.subject.meaning_mnemonic.clone(),
-            );
-            attr.insert(
-                "primary-reading-mnemonic".to_owned(),
-                self.reading_mnemonic.clone(),
-            );
-            attr.insert(
-                "reference-url".to_owned(),
-                self.subject.document_url.clone(),
-            );
+    fn render(fields: &[ConfigurationFieldOptions], name: &str, subject: &Value) -> String {
+        let field = fields
+            .iter()
+            .find(|f| f.name == name)
+            .unwrap_or_else(|| panic!("no field named {}", name));
+        field.render(subject).unwrap()
+    }
 
-            let alternative_meanings = self
-                .subject
-                .meanings
-                .iter()
-                .filter(|m| !m.primary)
-                .map(|m| m.meaning.clone())
-                .collect::<Vec<_>>()
-                .join(", ");
-            attr.insert("secondary-meanings".to_owned(), alternative_meanings);
-
-            let primary_reading = self
-                .readings
-                .iter()
-                .find(|r| r.primary)
-                .expect("vocabulary must have a reading marked primary");
-            attr.insert(
-                "primary-reading".to_owned(),
-                primary_reading.reading.clone(),
-            );
+    fn context_sentence_fields(fields: &[ConfigurationFieldOptions]) -> Vec<&str> {
+        fields
+            .iter()
+            .map(|f| f.name.as_str())
+            .filter(|name| name.starts_with("context-sentence-"))
+            .collect()
+    }
 
-            let secondary_readings = self
-                .readings
-                .iter()
-                .filter(|r| !r.primary)
-                .map(|r| r.reading.clone())
-                .collect::<Vec<_>>()
-                .join(", ");
-            attr.insert("readings".to_owned(), secondary_readings);
-
-            let relevant_context_sentences = self.context_sentences.iter().take(3);
-            for (i, sentence) in relevant_context_sentences.enumerate() {
-                attr.insert(format!("context-sentence-{}-ja", i), sentence.ja.clone());
-                attr.insert(format!("context-sentence-{}-en", i), sentence.en.clone());
-            }
+    /// The properties of a subject as field templates see them, sorted.
+    fn properties(subject: &Value) -> Vec<String> {
+        let mut keys = subject
+            .as_object()
+            .unwrap()
+            .keys()
+            .cloned()
+            .collect::<Vec<_>>();
+        keys.sort();
+        keys
+    }
+
+    fn sorted(lists: &[&[&str]]) -> Vec<String> {
+        let mut names = lists
+            .concat()
+            .into_iter()
+            .map(str::to_owned)
+            .collect::<Vec<_>>();
+        names.sort();
+        names
+    }
+
+    #[test]
+    fn kanji_properties_match_the_serialized_kanji() {
+        assert_eq!(
+            properties(&to_json(&ApiKanjiMessage::default())),
+            sorted(&[&ApiSubjectMessage::PROPERTIES, &ApiKanjiMessage::PROPERTIES])
+        );
+    }
+
+    #[test]
+    fn vocabulary_properties_match_the_serialized_vocabulary() {
+        let mut subject = to_json(&ApiVocabularyMessage::default());
+        // Added when building the note, see `ApiVocabularyMessage::into_anki_input`.
+        subject["with_furigana"] = json!({});
+        assert_eq!(
+            properties(&subject),
+            sorted(&[
+                &ApiSubjectMessage::PROPERTIES,
+                &ApiVocabularyMessage::PROPERTIES
+            ])
+        );
+    }
 
-            attr
+    #[test]
+    fn context_sentence_fields_are_numbered_from_one() {
+        let fields = default_vocabulary_fields(2);
+        assert_eq!(
+            context_sentence_fields(&fields),
+            [
+                "context-sentence-1-en",
+                "context-sentence-1-ja",
+                "context-sentence-1-ja-furigana",
+                "context-sentence-2-en",
+                "context-sentence-2-ja",
+                "context-sentence-2-ja-furigana",
+            ]
+        );
+    }
+
+    #[test]
+    fn context_sentence_fields_follow_the_configured_count() {
+        assert!(context_sentence_fields(&default_vocabulary_fields(0)).is_empty());
+        assert_eq!(
+            context_sentence_fields(&default_vocabulary_fields(5)).len(),
+            15
+        );
+    }
+
+    #[test]
+    fn first_context_sentence_field_holds_the_first_sentence() {
+        let fields = default_vocabulary_fields(3);
+        let subject = vocabulary(3);
+        assert_eq!(render(&fields, "context-sentence-1-en", &subject), "en 1");
+        assert_eq!(render(&fields, "context-sentence-1-ja", &subject), "ja 1");
+        assert_eq!(
+            render(&fields, "context-sentence-1-ja-furigana", &subject),
+            "furigana 1"
+        );
+    }
+
+    #[test]
+    fn last_context_sentence_field_holds_the_last_sentence() {
+        let fields = default_vocabulary_fields(3);
+        let subject = vocabulary(3);
+        assert_eq!(render(&fields, "context-sentence-3-en", &subject), "en 3");
+        assert_eq!(render(&fields, "context-sentence-3-ja", &subject), "ja 3");
+    }
+
+    #[test]
+    fn context_sentence_fields_beyond_the_available_sentences_are_empty() {
+        let fields = default_vocabulary_fields(5);
+        let subject = vocabulary(2);
+        assert_eq!(render(&fields, "context-sentence-2-en", &subject), "en 2");
+        assert_eq!(render(&fields, "context-sentence-3-en", &subject), "");
+        assert_eq!(render(&fields, "context-sentence-5-ja", &subject), "");
+    }
+
+    #[test]
+    fn vocabulary_back_template_lists_the_configured_sentences() {
+        assert!(matches!(
+            vocabulary_back_template(DEFAULT_CONTEXT_SENTENCES),
+            Resource::Builtin(_)
+        ));
+        let Resource::Generated(template) = vocabulary_back_template(5) else {
+            panic!("expected a generated template");
         };
-        AddNoteInput {
-            note: AddNoteNoteMessage {
-                deck_name: deck_name.to_owned(),
-                model_name: model_name.to_owned(),
-                tags: vec!["WaniKani Vocabulary".to_owned()],
-                audio: vec![],
-                picture: vec![],
-                video: vec![],
-                fields: attributes,
-            },
-        }
+        assert!(template.contains("{{context-sentence-5-en}}"));
+        assert!(template.contains("{{furigana:context-sentence-5-ja-furigana}}"));
+        assert!(!template.contains("context-sentence-6"));
+        let Resource::Generated(template) = vocabulary_back_template(1) else {
+            panic!("expected a generated template");
+        };
+        assert!(template.contains("{{context-sentence-1-en}}"));
+        assert!(!template.contains("context-sentence-2"));
     }
 }
@@ -1,5 +1,5 @@
 use crate::anki_connect::rpc::{AnkiRequest, CommandRequest, CommandResponse};
-use reqwest::Client;
+use reqwest::{Client, StatusCode};
 use std::time::Duration;
 use thiserror::Error;
 
@@ -9,12 +9,51 @@ pub enum AnkiError {
     HttpError(#[from] reqwest::Error),
     #[error("anki data serde error: {0}")]
     SerdeError(#[from] serde_json::Error),
+    #[error("anki endpoint responded with status {0}")]
+    ServerError(StatusCode),
     #[error("anki endpoint returned nothing")]
     EmptyResponse,
     #[error("anki endpoint returned error: {0}")]
     ApiError(String),
     #[error("io error: {0}")]
     Io(#[from] tokio::io::Error),
+    #[error(
+        "model '{model}' is missing the fields [{}], run `wanikanji update-model-fields` to add them (fields of the model that are not generated: [{}])",
+        .missing.join(", "),
+        .unexpected.join(", ")
+    )]
+    FieldMismatch {
+        model: String,
+        /// Generated fields that the model does not have.
+        missing: Vec<String>,
+        /// Fields of the model that are not generated.
+        unexpected: Vec<String>,
+    },
+}
+
+impl AnkiError {
+    /// A stable identifier of the kind of error, for scripts that consume the JSON output.
+    pub fn code(&self) -> &'static str {
+        match self {
+            AnkiError::HttpError(e) if e.is_connect() => "anki-unreachable",
+            AnkiError::HttpError(_) => "anki-http-error",
+            AnkiError::SerdeError(_) => "anki-invalid-response",
+            AnkiError::ServerError(_) => "anki-server-error",
+            AnkiError::EmptyResponse => "anki-empty-response",
+            AnkiError::ApiError(_) => "anki-api-error",
+            AnkiError::Io(_) => "anki-io-error",
+            AnkiError::FieldMismatch { .. } => "anki-field-mismatch",
+        }
+    }
+
+    /// Whether the request may succeed when sent again, because Anki Connect was unreachable or overwhelmed.
+    pub fn is_transient(&self) -> bool {
+        match self {
+            AnkiError::HttpError(e) => e.is_connect(),
+            AnkiError::ServerError(_) => true,
+            _ => false,
+        }
+    }
 }
 
 pub struct AnkiClient<'a> {
@@ -57,6 +96,9 @@ impl<'a> AnkiClient<'a> {
             .json(&request)
             .send()
             .await?;
+        if response.status().is_server_error() {
+            return Err(AnkiError::ServerError(response.status()));
+        }
         let response = response.json::<CommandResponse<T::Response>>().await?;
 
         match (response.result, response.error) {
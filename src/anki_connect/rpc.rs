@@ -227,3 +227,272 @@ pub mod update_model_templates {
         const ACTION: &'static str = "updateModelTemplates";
     }
 }
+
+pub mod find_notes {
+    use crate::anki_connect::rpc::AnkiRequest;
+    use serde::Serialize;
+
+    #[derive(Debug, Serialize, Clone)]
+    #[serde(rename_all = "camelCase")]
+    pub struct FindNotesInput {
+        pub query: String,
+    }
+
+    impl AnkiRequest for FindNotesInput {
+        type Response = Vec<i64>;
+
+        const VERSION: u16 = 6;
+        const ACTION: &'static str = "findNotes";
+    }
+}
+
+pub mod notes_info {
+    use crate::anki_connect::rpc::AnkiRequest;
+    use serde::{Deserialize, Serialize};
+    use std::collections::HashMap;
+
+    #[derive(Debug, Serialize, Clone)]
+    #[serde(rename_all = "camelCase")]
+    pub struct NotesInfoInput {
+        pub notes: Vec<i64>,
+    }
+
+    #[derive(Debug, Deserialize)]
+    #[serde(rename_all = "camelCase")]
+    pub struct NotesInfoNoteMessage {
+        pub note_id: i64,
+        pub model_name: String,
+        #[serde(default)]
+        pub tags: Vec<String>,
+        pub fields: HashMap<String, NotesInfoFieldMessage>,
+        #[serde(default)]
+        pub cards: Vec<i64>,
+    }
+
+    #[derive(Debug, Deserialize)]
+    #[serde(rename_all = "camelCase")]
+    pub struct NotesInfoFieldMessage {
+        pub value: String,
+        pub order: i64,
+    }
+
+    impl AnkiRequest for NotesInfoInput {
+        type Response = Vec<NotesInfoNoteMessage>;
+
+        const VERSION: u16 = 6;
+        const ACTION: &'static str = "notesInfo";
+    }
+}
+
+pub mod update_note_fields {
+    use crate::anki_connect::rpc::AnkiRequest;
+    use serde::Serialize;
+    use std::collections::HashMap;
+
+    #[derive(Debug, Serialize, Clone)]
+    #[serde(rename_all = "camelCase")]
+    pub struct UpdateNoteFieldsInput {
+        pub note: UpdateNoteFieldsNoteMessage,
+    }
+
+    #[derive(Debug, Serialize, Clone)]
+    #[serde(rename_all = "camelCase")]
+    pub struct UpdateNoteFieldsNoteMessage {
+        pub id: i64,
+        pub fields: HashMap<String, String>,
+    }
+
+    impl AnkiRequest for UpdateNoteFieldsInput {
+        type Response = ();
+
+        const VERSION: u16 = 6;
+        const ACTION: &'static str = "updateNoteFields";
+    }
+}
+
+pub mod change_deck {
+    use crate::anki_connect::rpc::AnkiRequest;
+    use serde::Serialize;
+
+    #[derive(Debug, Serialize, Clone)]
+    #[serde(rename_all = "camelCase")]
+    pub struct ChangeDeckInput {
+        pub cards: Vec<i64>,
+        pub deck: String,
+    }
+
+    impl AnkiRequest for ChangeDeckInput {
+        type Response = ();
+
+        const VERSION: u16 = 6;
+        const ACTION: &'static str = "changeDeck";
+    }
+}
+
+pub mod model_templates {
+    use crate::anki_connect::rpc::AnkiRequest;
+    use serde::{Deserialize, Serialize};
+    use std::collections::HashMap;
+
+    #[derive(Debug, Serialize, Clone)]
+    #[serde(rename_all = "camelCase")]
+    pub struct ModelTemplatesInput {
+        pub model_name: String,
+    }
+
+    #[derive(Debug, Deserialize)]
+    pub struct ModelTemplatesCardTemplateMessage {
+        #[serde(rename = "Front")]
+        pub front: String,
+        #[serde(rename = "Back")]
+        pub back: String,
+    }
+
+    impl AnkiRequest for ModelTemplatesInput {
+        type Response = HashMap<String, ModelTemplatesCardTemplateMessage>;
+
+        const VERSION: u16 = 6;
+        const ACTION: &'static str = "modelTemplates";
+    }
+}
+
+pub mod model_template_add {
+    use crate::anki_connect::rpc::create_model::CreateModelCardTemplateMessage;
+    use crate::anki_connect::rpc::AnkiRequest;
+    use serde::Serialize;
+
+    #[derive(Debug, Serialize)]
+    #[serde(rename_all = "camelCase")]
+    pub struct ModelTemplateAddInput {
+        pub model_name: String,
+        pub template: CreateModelCardTemplateMessage,
+    }
+
+    impl AnkiRequest for ModelTemplateAddInput {
+        type Response = ();
+
+        const VERSION: u16 = 6;
+        const ACTION: &'static str = "modelTemplateAdd";
+    }
+}
+
+pub mod model_template_remove {
+    use crate::anki_connect::rpc::AnkiRequest;
+    use serde::Serialize;
+
+    #[derive(Debug, Serialize, Clone)]
+    #[serde(rename_all = "camelCase")]
+    pub struct ModelTemplateRemoveInput {
+        pub model_name: String,
+        pub template_name: String,
+    }
+
+    impl AnkiRequest for ModelTemplateRemoveInput {
+        type Response = ();
+
+        const VERSION: u16 = 6;
+        const ACTION: &'static str = "modelTemplateRemove";
+    }
+}
+
+pub mod model_field_names {
+    use crate::anki_connect::rpc::AnkiRequest;
+    use serde::Serialize;
+
+    #[derive(Debug, Serialize, Clone)]
+    #[serde(rename_all = "camelCase")]
+    pub struct ModelFieldNamesInput {
+        pub model_name: String,
+    }
+
+    impl AnkiRequest for ModelFieldNamesInput {
+        type Response = Vec<String>;
+
+        const VERSION: u16 = 6;
+        const ACTION: &'static str = "modelFieldNames";
+    }
+}
+
+pub mod model_field_add {
+    use crate::anki_connect::rpc::AnkiRequest;
+    use serde::Serialize;
+
+    #[derive(Debug, Serialize, Clone)]
+    #[serde(rename_all = "camelCase")]
+    pub struct ModelFieldAddInput {
+        pub model_name: String,
+        pub field_name: String,
+        pub index: usize,
+    }
+
+    impl AnkiRequest for ModelFieldAddInput {
+        type Response = ();
+
+        const VERSION: u16 = 6;
+        const ACTION: &'static str = "modelFieldAdd";
+    }
+}
+
+pub mod model_field_remove {
+    use crate::anki_connect::rpc::AnkiRequest;
+    use serde::Serialize;
+
+    #[derive(Debug, Serialize, Clone)]
+    #[serde(rename_all = "camelCase")]
+    pub struct ModelFieldRemoveInput {
+        pub model_name: String,
+        pub field_name: String,
+    }
+
+    impl AnkiRequest for ModelFieldRemoveInput {
+        type Response = ();
+
+        const VERSION: u16 = 6;
+        const ACTION: &'static str = "modelFieldRemove";
+    }
+}
+
+pub mod model_names {
+    use crate::anki_connect::rpc::AnkiRequest;
+    use serde::Serialize;
+
+    #[derive(Debug, Serialize, Clone)]
+    pub struct ModelNamesInput {}
+
+    impl AnkiRequest for ModelNamesInput {
+        type Response = Vec<String>;
+
+        const VERSION: u16 = 6;
+        const ACTION: &'static str = "modelNames";
+    }
+}
+
+pub mod deck_names {
+    use crate::anki_connect::rpc::AnkiRequest;
+    use serde::Serialize;
+
+    #[derive(Debug, Serialize, Clone)]
+    pub struct DeckNamesInput {}
+
+    impl AnkiRequest for DeckNamesInput {
+        type Response = Vec<String>;
+
+        const VERSION: u16 = 6;
+        const ACTION: &'static str = "deckNames";
+    }
+}
+
+pub mod version {
+    use crate::anki_connect::rpc::AnkiRequest;
+    use serde::Serialize;
+
+    #[derive(Debug, Serialize, Clone)]
+    pub struct VersionInput {}
+
+    impl AnkiRequest for VersionInput {
+        type Response = u16;
+
+        const VERSION: u16 = 6;
+        const ACTION: &'static str = "version";
+    }
+}
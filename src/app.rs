@@ -1,27 +1,82 @@
+use crate::anki::ConversionError;
 use crate::anki_connect::client::{AnkiClient, AnkiError};
-use crate::io::FilesystemCache;
-use crate::kanji::ApiKanjiMessage;
+use crate::anki_connect::rpc::add_note::AddNoteInput;
+use crate::checkpoint::{InstallCheckpoint, SubjectStatus};
+use crate::configuration::{Configuration, ConfigurationDeckOptions, ConfigurationLayers};
+use crate::doctor::DoctorReport;
+use crate::furigana::FuriganaDictionary;
+use crate::init::InitReport;
+use crate::io::{Cache, IoError, SubjectFilter};
+use crate::kanji::{ApiKanjiMessage, KANJI_URL};
+use crate::plan::{escape_search, NoteAction};
+use crate::progress::{NoteCounts, Progress, Summary};
 use crate::query::QueryClient;
-use crate::vocabulary::ApiVocabularyMessage;
-use crate::Configuration;
+use crate::resources::export_resources;
+use crate::status::{CacheStatus, DeckStatus, StatusReport};
+use crate::vocabulary::{ApiVocabularyMessage, VOCABULARY_URL};
+use crate::{
+    ExportResourcesOptions, InitOptions, InstallOptions, Options, UpdateModelFieldsOptions,
+    UpdateModelTemplatesOptions,
+};
+use again::RetryPolicy;
+use futures::{stream, StreamExt};
+use std::future::Future;
+use std::path::Path;
+use std::time::Duration;
+use thiserror::Error;
+
+/// Failures of a command that completed its work but could not do all of it.
+#[derive(Debug, Error)]
+pub enum CommandError {
+    #[error("{failed} of {total} subjects failed to install into '{deck}'")]
+    InstallFailed {
+        failed: usize,
+        total: usize,
+        deck: String,
+    },
+    #[error("{failed} of {total} checks failed")]
+    DoctorFailed { failed: usize, total: usize },
+}
+
+impl CommandError {
+    /// A stable identifier of the kind of error, for scripts that consume the JSON output.
+    pub fn code(&self) -> &'static str {
+        match self {
+            CommandError::InstallFailed { .. } => "install-failed",
+            CommandError::DoctorFailed { .. } => "doctor-failed",
+        }
+    }
+}
 
 /// Handle `wanikanji query-kanji` command
 pub async fn handle_query_kanji(
-    cache: &FilesystemCache<'_>,
+    cache: &impl Cache,
     wanikani_client: &QueryClient,
+    summary: &mut Summary,
 ) -> anyhow::Result<()> {
-    let kanji = wanikani_client.list_kanji().await?;
-    cache.insert("kanji", &kanji).await?;
+    let progress = Progress::new("fetching kanji", "pages");
+    let kanji = wanikani_client.list_kanji(&progress).await?;
+    progress.finish();
+    summary.pages_fetched = Some(progress.position() as usize);
+    summary.subjects_fetched = Some(kanji.len());
+    cache.insert("kanji", KANJI_URL, &kanji).await?;
     Ok(())
 }
 
 /// Handle `wanikanji query-vocabulary` command
 pub async fn handle_query_vocabulary(
-    cache: &FilesystemCache<'_>,
+    cache: &impl Cache,
     wanikani_client: &QueryClient,
+    summary: &mut Summary,
 ) -> anyhow::Result<()> {
-    let vocabulary = wanikani_client.list_vocabulary().await?;
-    cache.insert("vocabulary", &vocabulary).await?;
+    let progress = Progress::new("fetching vocabulary", "pages");
+    let vocabulary = wanikani_client.list_vocabulary(&progress).await?;
+    progress.finish();
+    summary.pages_fetched = Some(progress.position() as usize);
+    summary.subjects_fetched = Some(vocabulary.len());
+    cache
+        .insert("vocabulary", VOCABULARY_URL, &vocabulary)
+        .await?;
     Ok(())
 }
 
@@ -30,12 +85,7 @@ pub async fn handle_create_kanji_deck(
     anki_client: &AnkiClient<'_>,
     configuration: &Configuration,
 ) -> anyhow::Result<()> {
-    anki_client
-        .create_kanji_model(
-            &configuration.kanji.model_name,
-            &configuration.kanji.model_template_name,
-        )
-        .await?;
+    anki_client.create_model(&configuration.kanji).await?;
     anki_client
         .create_deck(&configuration.kanji.deck_name)
         .await?;
@@ -47,12 +97,7 @@ pub async fn handle_create_vocabulary_deck(
     anki_client: &AnkiClient<'_>,
     configuration: &Configuration,
 ) -> anyhow::Result<()> {
-    anki_client
-        .create_vocabulary_model(
-            &configuration.vocabulary.model_name,
-            &configuration.vocabulary.model_template_name,
-        )
-        .await?;
+    anki_client.create_model(&configuration.vocabulary).await?;
     anki_client
         .create_deck(&configuration.vocabulary.deck_name)
         .await?;
@@ -65,10 +110,10 @@ pub async fn handle_update_model_styling(
     configuration: &Configuration,
 ) -> anyhow::Result<()> {
     anki_client
-        .update_model_styling(&configuration.kanji.model_name)
+        .update_model_styling(&configuration.kanji)
         .await?;
     anki_client
-        .update_model_styling(&configuration.vocabulary.model_name)
+        .update_model_styling(&configuration.vocabulary)
         .await?;
     Ok(())
 }
@@ -77,51 +122,62 @@ pub async fn handle_update_model_styling(
 pub async fn handle_update_model_templates(
     anki_client: &AnkiClient<'_>,
     configuration: &Configuration,
+    options: &UpdateModelTemplatesOptions,
+) -> anyhow::Result<()> {
+    anki_client
+        .update_model_templates(&configuration.kanji, options.prune)
+        .await?;
+    anki_client
+        .update_model_templates(&configuration.vocabulary, options.prune)
+        .await?;
+    Ok(())
+}
+
+/// Handle `wanikanji update-model-fields` command
+pub async fn handle_update_model_fields(
+    anki_client: &AnkiClient<'_>,
+    configuration: &Configuration,
+    options: &UpdateModelFieldsOptions,
 ) -> anyhow::Result<()> {
     anki_client
-        .update_model_templates(&configuration.kanji)
+        .update_model_fields(&configuration.kanji, options.prune)
         .await?;
     anki_client
-        .update_model_templates(&configuration.vocabulary)
+        .update_model_fields(&configuration.vocabulary, options.prune)
         .await?;
     Ok(())
 }
 
 /// Handle `wanikanji install-kanji` command
 pub async fn handle_install_kanji(
-    cache: &FilesystemCache<'_>,
+    cache: &impl Cache,
+    wanikani_client: &QueryClient,
     anki_client: &AnkiClient<'_>,
     configuration: &Configuration,
+    options: &InstallOptions,
+    summary: &mut Summary,
 ) -> anyhow::Result<()> {
-    let kanji = cache.get::<Vec<ApiKanjiMessage>>("kanji").await?;
+    let filter = subject_filter(options);
+    let kanji = cached_subjects::<ApiKanjiMessage, _>(cache, "kanji", &filter, || {
+        handle_query_kanji(cache, wanikani_client, summary)
+    })
+    .await?;
     match kanji {
         Some(kanji) => {
-            for kanji in kanji {
-                let input = kanji.into_anki_input(
-                    &configuration.kanji.model_name,
-                    &configuration.kanji.deck_name,
-                );
-                // SAFETY: This function has to perform a retry loop, because the Anki Connect API server tends to
-                // become overwhelmed with requests when it's fired off rapidly at the speed tokio+reqwest can perform.
-                fn is_connection_error(e: &AnkiError) -> bool {
-                    matches!(e, AnkiError::HttpError(e) if e.is_connect())
-                }
-                again::retry_if(
-                    || async {
-                        match anki_client.send(input.clone()).await {
-                            Ok(_) => Ok(()),
-                            Err(AnkiError::ApiError(err))
-                                if err.contains("cannot create note because it is a duplicate") =>
-                            {
-                                Ok(())
-                            }
-                            Err(e) => Err(e),
-                        }
-                    },
-                    is_connection_error,
-                )
-                .await?;
-            }
+            let inputs = kanji
+                .into_iter()
+                .map(|kanji| kanji.into_anki_input(&configuration.kanji))
+                .collect();
+            install_notes(
+                cache,
+                anki_client,
+                &configuration.kanji,
+                "kanji-install-checkpoint",
+                inputs,
+                options,
+                summary,
+            )
+            .await?;
         }
         None => {
             tracing::error!("you must fetch kanji information before installing to deck")
@@ -132,39 +188,41 @@ pub async fn handle_install_kanji(
 
 /// Handle `wanikanji install-vocabulary` command
 pub async fn handle_install_vocabulary(
-    cache: &FilesystemCache<'_>,
+    cache: &impl Cache,
+    wanikani_client: &QueryClient,
     anki_client: &AnkiClient<'_>,
     configuration: &Configuration,
+    options: &InstallOptions,
+    summary: &mut Summary,
 ) -> anyhow::Result<()> {
-    let vocabulary = cache.get::<Vec<ApiVocabularyMessage>>("vocabulary").await?;
+    // Every vocabulary is read, even when only a few are installed, because they all contribute to the furigana.
+    let vocabulary = cached_subjects::<ApiVocabularyMessage, _>(
+        cache,
+        "vocabulary",
+        &SubjectFilter::default(),
+        || handle_query_vocabulary(cache, wanikani_client, summary),
+    )
+    .await?;
     match vocabulary {
         Some(vocabulary) => {
-            for vocabulary in vocabulary {
-                let input = vocabulary.into_anki_input(
-                    &configuration.vocabulary.model_name,
-                    &configuration.vocabulary.deck_name,
-                );
-                // SAFETY: This function has to perform a retry loop, because the Anki Connect API server tends to
-                // become overwhelmed with requests when it's fired off rapidly at the speed tokio+reqwest can perform.
-                fn is_connection_error(e: &AnkiError) -> bool {
-                    matches!(e, AnkiError::HttpError(e) if e.is_connect())
-                }
-                again::retry_if(
-                    || async {
-                        match anki_client.send(input.clone()).await {
-                            Ok(_) => Ok(()),
-                            Err(AnkiError::ApiError(err))
-                                if err.contains("cannot create note because it is a duplicate") =>
-                            {
-                                Ok(())
-                            }
-                            Err(e) => Err(e),
-                        }
-                    },
-                    is_connection_error,
-                )
-                .await?;
-            }
+            // The kanji readings improve the furigana, but they are not required to install vocabulary.
+            let kanji = get_or_default::<Vec<ApiKanjiMessage>>(cache, "kanji").await?;
+            let inputs = vocabulary_inputs(
+                vocabulary,
+                &kanji,
+                &configuration.vocabulary,
+                &subject_filter(options),
+            );
+            install_notes(
+                cache,
+                anki_client,
+                &configuration.vocabulary,
+                "vocabulary-install-checkpoint",
+                inputs,
+                options,
+                summary,
+            )
+            .await?;
         }
         None => {
             tracing::error!("you must fetch vocabulary information before installing to deck")
@@ -172,3 +230,503 @@ pub async fn handle_install_vocabulary(
     }
     Ok(())
 }
+
+/// Build the notes of the vocabulary that match the filter.
+///
+/// The furigana is aligned against every vocabulary rather than only the filtered ones, so that installing a few
+/// subjects writes the same notes as installing all of them.
+fn vocabulary_inputs(
+    vocabulary: Vec<ApiVocabularyMessage>,
+    kanji: &[ApiKanjiMessage],
+    deck: &ConfigurationDeckOptions,
+    filter: &SubjectFilter,
+) -> Vec<Result<AddNoteInput, ConversionError>> {
+    let furigana = FuriganaDictionary::new(&vocabulary, kanji);
+    vocabulary
+        .into_iter()
+        .filter(|vocabulary| filter.matches_subject(&vocabulary.subject))
+        .map(|vocabulary| vocabulary.into_anki_input(deck, &furigana))
+        .collect()
+}
+
+/// Handle `wanikanji status` command
+pub async fn handle_status(
+    cache: &impl Cache,
+    anki_client: &AnkiClient<'_>,
+    configuration: &Configuration,
+    summary: &mut Summary,
+) -> anyhow::Result<()> {
+    let kanji = get_for_status::<ApiKanjiMessage>(cache, "kanji").await?;
+    let vocabulary = get_for_status::<ApiVocabularyMessage>(cache, "vocabulary").await?;
+    let kanji_cache = CacheStatus::load(cache, "kanji", kanji.as_deref(), |k| &k.subject).await?;
+    let vocabulary_cache =
+        CacheStatus::load(cache, "vocabulary", vocabulary.as_deref(), |v| &v.subject).await?;
+    let (kanji, vocabulary) = (kanji.unwrap_or_default(), vocabulary.unwrap_or_default());
+
+    let (model_names, deck_names) = match anki_client.model_names().await {
+        Ok(model_names) => (model_names, anki_client.deck_names().await?),
+        Err(e @ AnkiError::HttpError(_)) if e.is_transient() => {
+            tracing::warn!("{}", e);
+            summary.status = Some(StatusReport {
+                cache_dir: cache.location().to_owned(),
+                cache: vec![kanji_cache, vocabulary_cache],
+                decks: vec![
+                    DeckStatus::unreachable(&configuration.kanji),
+                    DeckStatus::unreachable(&configuration.vocabulary),
+                ],
+            });
+            return Ok(());
+        }
+        Err(e) => return Err(e.into()),
+    };
+    let furigana = FuriganaDictionary::new(&vocabulary, &kanji);
+    let kanji_notes = kanji
+        .into_iter()
+        .map(|kanji| kanji.into_anki_input(&configuration.kanji))
+        .collect();
+    let vocabulary_notes = vocabulary
+        .into_iter()
+        .map(|vocabulary| vocabulary.into_anki_input(&configuration.vocabulary, &furigana))
+        .collect();
+    let decks = vec![
+        deck_status(
+            anki_client,
+            &configuration.kanji,
+            kanji_cache.subjects.map(|_| kanji_notes),
+            &model_names,
+            &deck_names,
+        )
+        .await?,
+        deck_status(
+            anki_client,
+            &configuration.vocabulary,
+            vocabulary_cache.subjects.map(|_| vocabulary_notes),
+            &model_names,
+            &deck_names,
+        )
+        .await?,
+    ];
+    summary.status = Some(StatusReport {
+        cache_dir: cache.location().to_owned(),
+        cache: vec![kanji_cache, vocabulary_cache],
+        decks,
+    });
+    Ok(())
+}
+
+/// Handle `wanikanji doctor` command
+pub async fn handle_doctor(options: &Options, summary: &mut Summary) -> anyhow::Result<()> {
+    let report = DoctorReport::diagnose(options).await;
+    let failures = report.failures();
+    let total = report.checks.len();
+    summary.doctor = Some(report);
+    if failures > 0 {
+        return Err(CommandError::DoctorFailed {
+            failed: failures,
+            total,
+        }
+        .into());
+    }
+    Ok(())
+}
+
+/// Handle `wanikanji init` command
+pub async fn handle_init(
+    options: &Options,
+    init: &InitOptions,
+    summary: &mut Summary,
+) -> anyhow::Result<()> {
+    // Failed connectivity checks do not fail the command, the configuration has been written and the report tells
+    // what to fix.
+    summary.init = Some(InitReport::run(options, init).await?);
+    Ok(())
+}
+
+/// Handle `wanikanji export-resources` command
+pub async fn handle_export_resources(
+    options: &ExportResourcesOptions,
+    summary: &mut Summary,
+) -> anyhow::Result<()> {
+    let written = export_resources(Path::new(&options.dir), options.force).await?;
+    summary.exported = written.iter().map(|p| p.display().to_string()).collect();
+    Ok(())
+}
+
+/// Handle `wanikanji config show` command
+pub fn handle_config_show(
+    layers: &ConfigurationLayers,
+    summary: &mut Summary,
+) -> anyhow::Result<()> {
+    summary.configuration = Some(layers.report()?);
+    Ok(())
+}
+
+/// Find out whether a configured deck exists in Anki, and how many of the cached subjects it is missing.
+async fn deck_status(
+    anki_client: &AnkiClient<'_>,
+    deck: &ConfigurationDeckOptions,
+    inputs: Option<Vec<Result<AddNoteInput, ConversionError>>>,
+    model_names: &[String],
+    deck_names: &[String],
+) -> anyhow::Result<DeckStatus> {
+    let model_exists = model_names.contains(&deck.model_name);
+    let deck_exists = deck_names.contains(&deck.deck_name);
+    let notes = if deck_exists {
+        let query = format!("\"deck:{}\"", escape_search(&deck.deck_name));
+        Some(anki_client.find_notes(query).await?.len())
+    } else {
+        None
+    };
+    let not_installed = match (inputs, model_exists) {
+        (Some(inputs), true) => {
+            let (notes, failures): (Vec<_>, Vec<_>) = inputs.into_iter().partition(Result::is_ok);
+            let notes = notes.into_iter().filter_map(Result::ok).collect();
+            let plan = anki_client
+                .plan_install(&deck.model_name, &deck.deck_name, deck.key_field(), notes)
+                .await?;
+            Some(failures.len() + plan.count(|a| matches!(a, NoteAction::Create)))
+        }
+        (Some(inputs), false) => Some(inputs.len()),
+        (None, _) => None,
+    };
+    Ok(DeckStatus {
+        deck_name: deck.deck_name.clone(),
+        model_name: deck.model_name.clone(),
+        anki_reachable: true,
+        deck_exists: Some(deck_exists),
+        model_exists: Some(model_exists),
+        notes,
+        not_installed,
+    })
+}
+
+/// The subjects that an install is limited to. Without any of the options, every subject is installed.
+fn subject_filter(options: &InstallOptions) -> SubjectFilter {
+    SubjectFilter {
+        ids: options.ids.clone(),
+        levels: options.levels.clone(),
+        characters: options.characters.clone(),
+    }
+}
+
+/// Read an item from the cache, falling back to the default value when it has not been downloaded, or has to be
+/// downloaded again.
+async fn get_or_default<T>(cache: &impl Cache, key: &str) -> Result<T, IoError>
+where
+    T: Default + for<'de> serde::Deserialize<'de>,
+{
+    match cache.get::<T>(key).await {
+        Ok(value) => Ok(value.unwrap_or_default()),
+        Err(IoError::CacheItemNotFound) => Ok(T::default()),
+        Err(e @ IoError::CacheOutdated { .. }) => {
+            tracing::warn!("{}, run query-{} to do so", e, key);
+            Ok(T::default())
+        }
+        Err(e) => Err(e),
+    }
+}
+
+/// Read the cached subjects that match the filter. Subjects cached in a layout that cannot be migrated are downloaded
+/// again first.
+async fn cached_subjects<T, F>(
+    cache: &impl Cache,
+    key: &str,
+    filter: &SubjectFilter,
+    download: impl FnOnce() -> F,
+) -> anyhow::Result<Option<Vec<T>>>
+where
+    T: for<'de> serde::Deserialize<'de>,
+    F: Future<Output = anyhow::Result<()>>,
+{
+    match cache.subjects::<T>(key, filter).await {
+        Err(e @ IoError::CacheOutdated { .. }) => {
+            tracing::warn!("{}", e);
+            download().await?;
+            Ok(cache.subjects::<T>(key, filter).await?)
+        }
+        subjects => Ok(subjects?),
+    }
+}
+
+/// Read cached subjects for `status`, which reports subjects cached in an outdated format as `None` instead of failing.
+async fn get_for_status<T>(cache: &impl Cache, key: &str) -> Result<Option<Vec<T>>, IoError>
+where
+    T: for<'de> serde::Deserialize<'de>,
+{
+    match cache.get::<Vec<T>>(key).await {
+        Ok(value) => Ok(Some(value.unwrap_or_default())),
+        Err(IoError::CacheItemNotFound) => Ok(Some(Vec::new())),
+        Err(IoError::CacheOutdated { .. }) => Ok(None),
+        Err(e) => Err(e),
+    }
+}
+
+/// Plan the installation of the given notes, and either report the plan in the summary or apply it.
+///
+/// Subjects that could not be converted into notes, and notes that Anki rejects, do not stop the install. They are
+/// reported once every other note has been installed. Progress is recorded in a checkpoint under `checkpoint_key`, so
+/// that an interrupted install skips the subjects it already installed.
+async fn install_notes(
+    cache: &impl Cache,
+    anki_client: &AnkiClient<'_>,
+    deck: &ConfigurationDeckOptions,
+    checkpoint_key: &str,
+    inputs: Vec<Result<AddNoteInput, ConversionError>>,
+    options: &InstallOptions,
+    summary: &mut Summary,
+) -> anyhow::Result<()> {
+    let mut checkpoint = InstallCheckpoint::load(cache, checkpoint_key, &deck.deck_name).await?;
+    if options.restart {
+        checkpoint.subjects.clear();
+    }
+    let total = inputs.len();
+    let mut failures = Vec::new();
+    let mut notes = Vec::new();
+    for input in inputs {
+        match input {
+            Ok(input) => notes.push(input),
+            Err(e) => failures.push(e.to_string()),
+        }
+    }
+    let key_field = deck.key_field();
+    let before = notes.len();
+    notes.retain(|note| !checkpoint.is_installed(&note.note.fields[key_field]));
+    let resumed = before - notes.len();
+    if resumed > 0 {
+        tracing::info!(
+            "resuming install from checkpoint, skipping {} installed subjects",
+            resumed
+        );
+    }
+
+    anki_client
+        .check_model_fields(&deck.model_name, &deck.field_names())
+        .await?;
+    let plan = anki_client
+        .plan_install(&deck.model_name, &deck.deck_name, key_field, notes)
+        .await?;
+    if options.dry_run {
+        summary.plan = Some(plan);
+    } else {
+        let mut counts = NoteCounts {
+            skipped: resumed,
+            failed: failures.len(),
+            ..Default::default()
+        };
+        let progress = Progress::new(&format!("installing into '{}'", deck.deck_name), "notes");
+        progress.set_length(plan.notes.len() as u64);
+        // Anki Connect tends to become overwhelmed when requests are fired off at the speed tokio+reqwest can perform,
+        // so only a bounded number of requests are in flight, and requests that fail transiently are retried with an
+        // exponential backoff. The jitter keeps concurrent requests from retrying in lockstep.
+        let policy = RetryPolicy::exponential(Duration::from_millis(200))
+            .with_jitter(true)
+            .with_max_delay(Duration::from_secs(10))
+            .with_max_retries(8);
+        let deck_name = &plan.deck_name;
+        let mut results = stream::iter(&plan.notes)
+            .map(|note| {
+                let policy = &policy;
+                async move {
+                    let result = policy
+                        .retry_if(
+                            || anki_client.apply_planned_note(deck_name, note),
+                            AnkiError::is_transient,
+                        )
+                        .await;
+                    (note, result)
+                }
+            })
+            .buffer_unordered(options.anki_concurrency.into());
+        let mut applied = 0;
+        while let Some((note, result)) = results.next().await {
+            progress.inc(1);
+            match result {
+                Ok(()) => {
+                    checkpoint.mark(&note.key, SubjectStatus::Installed);
+                    counts.record(&note.action);
+                }
+                Err(e @ AnkiError::ApiError(_)) => {
+                    checkpoint.mark(&note.key, SubjectStatus::Failed);
+                    failures.push(format!("{}: {}", note.key, e));
+                    counts.failed += 1;
+                }
+                Err(e) => {
+                    drop(results);
+                    progress.finish();
+                    // The notes applied so far did change Anki, which the summary reports even though the install
+                    // stops here.
+                    summary.notes = Some(counts);
+                    checkpoint.save(cache).await?;
+                    return Err(e.into());
+                }
+            }
+            applied += 1;
+            if applied % CHECKPOINT_INTERVAL == 0 {
+                checkpoint.save(cache).await?;
+            }
+        }
+        progress.finish();
+        summary.notes = Some(counts);
+    }
+
+    if failures.is_empty() {
+        if !options.dry_run {
+            checkpoint.clear(cache).await?;
+        }
+        return Ok(());
+    }
+    if !options.dry_run {
+        checkpoint.save(cache).await?;
+    }
+    for failure in &failures {
+        tracing::error!("{}", failure);
+    }
+    let failed = failures.len();
+    summary.failures = failures;
+    Err(CommandError::InstallFailed {
+        failed,
+        total,
+        deck: deck.deck_name.clone(),
+    }
+    .into())
+}
+
+/// The number of notes installed between each write of the install checkpoint.
+const CHECKPOINT_INTERVAL: usize = 50;
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::io::{CacheCompression, FilesystemCache, CACHE_SCHEMA_VERSION};
+    use serde_json::{json, Value};
+    use std::cell::Cell;
+    use std::collections::HashMap;
+    use std::path::PathBuf;
+
+    /// An empty cache directory that is unique to the test.
+    fn cache_dir(test: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("wanikanji-{}-{}", test, std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    fn vocabulary(
+        id: i64,
+        characters: &str,
+        reading: &str,
+        sentence: &str,
+    ) -> ApiVocabularyMessage {
+        serde_json::from_value(json!({
+            "id": id,
+            "auxiliary_meanings": [],
+            "characters": characters,
+            "created_at": "2024-01-01T00:00:00Z",
+            "document_url": "",
+            "lesson_position": 0,
+            "level": 1,
+            "meaning_mnemonic": "",
+            "meanings": [{ "meaning": characters, "primary": true, "accepted_answer": true }],
+            "slug": characters,
+            "spaced_repetition_system_id": 1,
+            "component_subject_ids": [],
+            "context_sentences": [{ "en": "", "ja": sentence }],
+            "parts_of_speech": [],
+            "pronunciation_audios": [],
+            "readings": [{ "reading": reading, "primary": true, "accepted_answer": true }],
+            "reading_mnemonic": "",
+        }))
+        .unwrap()
+    }
+
+    fn fields(inputs: Vec<Result<AddNoteInput, ConversionError>>) -> Vec<HashMap<String, String>> {
+        inputs
+            .into_iter()
+            .map(|input| input.unwrap().note.fields)
+            .collect()
+    }
+
+    #[test]
+    fn filtered_vocabulary_gets_the_same_furigana_as_all_vocabulary() {
+        let all = || {
+            vec![
+                vocabulary(1, "日本", "にほん", "日本です"),
+                vocabulary(2, "食べる", "たべる", "日本で食べた"),
+            ]
+        };
+        let mut deck = serde_json::from_value::<ConfigurationDeckOptions>(json!({
+            "deck_name": "Vocabulary",
+            "model_name": "Vocabulary",
+        }))
+        .unwrap();
+        deck.fields = crate::anki::default_vocabulary_fields(1);
+        let filter = SubjectFilter {
+            ids: vec![2],
+            ..Default::default()
+        };
+
+        let unfiltered = fields(vocabulary_inputs(
+            all(),
+            &[],
+            &deck,
+            &SubjectFilter::default(),
+        ));
+        let filtered = fields(vocabulary_inputs(all(), &[], &deck, &filter));
+
+        assert_eq!(filtered, &unfiltered[1..]);
+        assert_eq!(
+            filtered[0]["context-sentence-1-ja-furigana"],
+            " 日本[にほん]で 食[た]べた"
+        );
+    }
+
+    #[tokio::test]
+    async fn outdated_subjects_are_downloaded_again() {
+        let dir = cache_dir("outdated-subjects");
+        let outdated = json!({ "schema_version": CACHE_SCHEMA_VERSION + 1, "data": [] });
+        std::fs::write(dir.join("kanji.json"), outdated.to_string()).unwrap();
+        let cache_dir = dir.display().to_string();
+        let cache = FilesystemCache::new(&cache_dir, CacheCompression::None)
+            .await
+            .unwrap();
+
+        let downloads = Cell::new(0);
+        let subjects =
+            cached_subjects::<Value, _>(&cache, "kanji", &SubjectFilter::default(), || {
+                downloads.set(downloads.get() + 1);
+                let cache = &cache;
+                async move {
+                    cache
+                        .insert("kanji", KANJI_URL, json!([{ "id": 1 }]))
+                        .await?;
+                    Ok(())
+                }
+            })
+            .await
+            .unwrap();
+
+        assert_eq!(downloads.get(), 1);
+        assert_eq!(subjects, Some(vec![json!({ "id": 1 })]));
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn bare_subjects_are_migrated_without_downloading() {
+        let dir = cache_dir("bare-subjects");
+        std::fs::write(dir.join("kanji.json"), json!([{ "id": 1 }]).to_string()).unwrap();
+        let cache_dir = dir.display().to_string();
+        let cache = FilesystemCache::new(&cache_dir, CacheCompression::None)
+            .await
+            .unwrap();
+
+        let subjects =
+            cached_subjects::<Value, _>(&cache, "kanji", &SubjectFilter::default(), || async {
+                anyhow::bail!("the subjects should not be downloaded")
+            })
+            .await
+            .unwrap();
+
+        assert_eq!(subjects, Some(vec![json!({ "id": 1 })]));
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+}
@@ -0,0 +1,60 @@
+use crate::io::{Cache, IoError};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+
+/// The source recorded for checkpoints, which wanikanji writes itself rather than downloading them.
+const CHECKPOINT_SOURCE: &str = "wanikanji install";
+
+/// The progress of an install, which lets an interrupted install resume where it stopped.
+///
+/// Subjects are identified by the value of the note's key field. The checkpoint is removed once an install completes
+/// without failures, so that the next install starts from the beginning again.
+#[derive(Debug, Default, Deserialize, Serialize)]
+pub struct InstallCheckpoint {
+    #[serde(skip)]
+    key: String,
+    pub deck_name: String,
+    pub subjects: HashMap<String, SubjectStatus>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Deserialize, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum SubjectStatus {
+    Installed,
+    Failed,
+}
+
+impl InstallCheckpoint {
+    /// Load the checkpoint stored under the given cache key. A missing checkpoint, or one that was recorded for
+    /// another deck, yields an empty checkpoint.
+    pub async fn load(cache: &impl Cache, key: &str, deck_name: &str) -> Result<Self, IoError> {
+        let checkpoint = match cache.get::<InstallCheckpoint>(key).await {
+            Ok(Some(checkpoint)) if checkpoint.deck_name == deck_name => checkpoint,
+            Ok(_) | Err(IoError::CacheItemNotFound | IoError::CacheOutdated { .. }) => {
+                InstallCheckpoint::default()
+            }
+            Err(e) => return Err(e),
+        };
+        Ok(Self {
+            key: key.to_owned(),
+            deck_name: deck_name.to_owned(),
+            ..checkpoint
+        })
+    }
+
+    pub fn is_installed(&self, subject: &str) -> bool {
+        self.subjects.get(subject) == Some(&SubjectStatus::Installed)
+    }
+
+    pub fn mark(&mut self, subject: &str, status: SubjectStatus) {
+        self.subjects.insert(subject.to_owned(), status);
+    }
+
+    pub async fn save(&self, cache: &impl Cache) -> Result<(), IoError> {
+        cache.insert(&self.key, CHECKPOINT_SOURCE, self).await
+    }
+
+    pub async fn clear(&self, cache: &impl Cache) -> Result<(), IoError> {
+        cache.remove(&self.key).await
+    }
+}
@@ -0,0 +1,727 @@
+use crate::anki;
+use crate::fields::FieldSource;
+use crate::io::{CacheBackend, CacheCompression};
+use crate::kanji::ApiKanjiMessage;
+use crate::query::ApiSubjectMessage;
+use crate::resources::Resource;
+use crate::template::{FieldTemplate, TemplateError};
+use crate::token::{ApiToken, TokenError};
+use crate::vocabulary::ApiVocabularyMessage;
+use crate::Options;
+use anyhow::Context;
+use config::{Config, ConfigError, Source};
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use std::path::{Path, PathBuf};
+use thiserror::Error;
+
+/// The name of the configuration file that is looked up in the working directory.
+const WORKING_DIRECTORY_FILE: &str = "wanikanji.toml";
+
+#[derive(Debug, Error)]
+pub enum ConfigurationError {
+    #[error("failed to read configuration from {origin}: {message}")]
+    Unreadable { origin: String, message: String },
+}
+
+/// Where a configuration value comes from, from the lowest to the highest priority.
+#[derive(Debug, Clone, PartialEq)]
+pub enum ConfigurationSource {
+    Default,
+    File(PathBuf),
+    Profile(String),
+    Environment(&'static str),
+    CommandLine,
+}
+
+/// The layers that make up the configuration, from the lowest to the highest priority.
+///
+/// Values of a higher layer replace the values of lower layers. The layers are the built-in defaults, the
+/// configuration file, the selected profile of the configuration file, environment variables and command line options.
+pub struct ConfigurationLayers {
+    layers: Vec<(ConfigurationSource, Result<Config, ConfigError>)>,
+    profile: Option<String>,
+}
+
+/// A value of the effective configuration, along with the layer it comes from.
+#[derive(Debug, Serialize)]
+pub struct ConfigurationValue {
+    pub key: String,
+    pub value: serde_json::Value,
+    pub source: String,
+}
+
+/// The effective configuration, as printed by `wanikanji config show`.
+#[derive(Debug, Serialize)]
+pub struct ConfigurationReport {
+    pub file: Option<String>,
+    pub profile: Option<String>,
+    pub values: Vec<ConfigurationValue>,
+}
+
+impl ConfigurationLayers {
+    /// Find the configuration file and collect every layer of the configuration.
+    ///
+    /// The configuration file is the first of `--config`, `$WANIKANJI_CONFIG`, `wanikanji.toml` in the working
+    /// directory and `$XDG_CONFIG_HOME/wanikanji/config.toml`. Without a configuration file, the built-in defaults are
+    /// used.
+    pub fn discover(options: &Options) -> Self {
+        let environment = ENVIRONMENT_VARIABLES
+            .into_iter()
+            .filter_map(|(variable, key)| Some((variable, key, std::env::var(variable).ok()?)))
+            .collect::<Vec<_>>();
+        let stdin_token = options.api_token_stdin.then(ApiToken::from_stdin);
+        Self::collect(
+            options,
+            find_configuration_file(options),
+            &environment,
+            stdin_token,
+        )
+    }
+
+    /// Collect the layers from the configuration file, the environment variables that are set and the API token read
+    /// from stdin, which `discover` looks up.
+    fn collect(
+        options: &Options,
+        file: Option<PathBuf>,
+        environment: &[(&'static str, &str, String)],
+        stdin_token: Option<Result<ApiToken, TokenError>>,
+    ) -> Self {
+        let mut layers = vec![(ConfigurationSource::Default, defaults())];
+        let file = file.map(|path| {
+            let file = Config::builder()
+                .add_source(config::File::from(path.as_path()))
+                .build();
+            (path, file)
+        });
+        let profile = options.profile.as_ref().map(|name| {
+            let profile = match &file {
+                Some((path, file)) => profile(name, path, file),
+                None => Err(ConfigError::Message(format!(
+                    "profile '{}' needs a configuration file that defines it",
+                    name
+                ))),
+            };
+            (ConfigurationSource::Profile(name.clone()), profile)
+        });
+        layers.extend(file.map(|(path, file)| (ConfigurationSource::File(path), file)));
+        layers.extend(profile);
+        for (variable, key, value) in environment {
+            let environment = Config::builder()
+                .set_override(*key, value.as_str())
+                .and_then(|b| b.build());
+            layers.push((ConfigurationSource::Environment(variable), environment));
+        }
+        let api_token = if let Some(stdin_token) = stdin_token {
+            match stdin_token {
+                Ok(token) => Some(token.expose().to_owned()),
+                Err(e) => {
+                    layers.push((
+                        ConfigurationSource::CommandLine,
+                        Err(ConfigError::Foreign(Box::new(e))),
+                    ));
+                    return Self {
+                        layers,
+                        profile: options.profile.clone(),
+                    };
+                }
+            }
+        } else {
+            options.api_token.clone()
+        };
+        let command_line = Config::builder()
+            .set_override_option("api_token", api_token)
+            .and_then(|b| b.set_override_option("anki_endpoint", options.anki_endpoint.clone()))
+            .and_then(|b| b.set_override_option("cache_dir", options.cache_dir.clone()))
+            .and_then(|b| b.build());
+        layers.push((ConfigurationSource::CommandLine, command_line));
+        Self {
+            layers,
+            profile: options.profile.clone(),
+        }
+    }
+
+    /// The name of the selected profile, if any.
+    pub fn profile(&self) -> Option<&str> {
+        self.profile.as_deref()
+    }
+
+    /// The configuration file in use, if any.
+    pub fn file(&self) -> Option<&Path> {
+        self.layers.iter().find_map(|(source, _)| match source {
+            ConfigurationSource::File(path) => Some(path.as_path()),
+            _ => None,
+        })
+    }
+
+    fn merge(&self) -> anyhow::Result<Config> {
+        let mut builder = Config::builder();
+        for (source, layer) in &self.layers {
+            let layer = layer.as_ref().map_err(|e| ConfigurationError::Unreadable {
+                origin: source.to_string(),
+                message: e.to_string(),
+            })?;
+            builder = builder.add_source(layer.clone());
+        }
+        Ok(builder.build()?)
+    }
+
+    /// Merge the layers into the effective configuration, filling in the built-in fields and templates of decks that
+    /// do not configure any.
+    pub fn load(&self) -> anyhow::Result<Configuration> {
+        let mut configuration = self
+            .merge()?
+            .try_deserialize::<Configuration>()
+            .context("failed to deserialize configuration")?;
+        if configuration.kanji.context_sentences.is_some() {
+            anyhow::bail!("context_sentences only applies to the vocabulary deck");
+        }
+        let context_sentences = configuration.vocabulary.context_sentences;
+        if context_sentences.is_some() && !configuration.vocabulary.fields.is_empty() {
+            anyhow::bail!(
+                "context_sentences only applies to the built-in vocabulary fields, configure the context sentence fields in vocabulary.fields instead"
+            );
+        }
+        let context_sentences = context_sentences.unwrap_or(anki::DEFAULT_CONTEXT_SENTENCES);
+        if configuration.kanji.fields.is_empty() {
+            configuration.kanji.fields = anki::default_kanji_fields();
+        }
+        if configuration.vocabulary.fields.is_empty() {
+            configuration.vocabulary.fields = anki::default_vocabulary_fields(context_sentences);
+        }
+        configuration.kanji.merge_legacy_template();
+        configuration.vocabulary.merge_legacy_template();
+        configuration.resolve_paths(self.base_dir());
+        configuration.cache_dir = self.profile_cache_dir(&configuration.cache_dir);
+        configuration.api_token = self.api_token()?;
+        if configuration.kanji.templates.is_empty() {
+            configuration.kanji.templates = anki::default_kanji_templates();
+        }
+        if configuration.vocabulary.templates.is_empty() {
+            configuration.vocabulary.templates =
+                anki::default_vocabulary_templates(context_sentences);
+        }
+        configuration.validate()?;
+        tracing::debug!("running wanikanji with configuration {:?}", &configuration);
+        Ok(configuration)
+    }
+
+    /// The directory that relative paths in the configuration are relative to.
+    fn base_dir(&self) -> &Path {
+        self.file()
+            .and_then(Path::parent)
+            .unwrap_or_else(|| Path::new("."))
+    }
+
+    /// The API token from the highest layer that sets either `api_token` or `token_file`, so that a profile with a
+    /// `token_file` is not overridden by an `api_token` of the file it is defined in.
+    pub fn api_token(&self) -> Result<Option<ApiToken>, TokenError> {
+        let layers = self
+            .layers
+            .iter()
+            .rev()
+            .filter_map(|(_, layer)| layer.as_ref().ok());
+        for layer in layers {
+            if let Ok(token) = layer.get_string("api_token") {
+                return Ok(Some(ApiToken::new(&token)));
+            }
+            if let Ok(path) = layer.get_string("token_file") {
+                return ApiToken::from_file(&self.base_dir().join(path)).map(Some);
+            }
+        }
+        Ok(None)
+    }
+
+    /// The cache directory from the highest layer that sets it, with the directory of the selected profile applied.
+    pub fn cache_dir(&self) -> Option<String> {
+        self.get_string("cache_dir")
+            .map(|cache_dir| self.profile_cache_dir(&cache_dir))
+    }
+
+    /// The directory inside `cache_dir` that the selected profile stores its data in.
+    fn profile_cache_dir(&self, cache_dir: &str) -> String {
+        match &self.profile {
+            // Profiles usually belong to different WaniKani accounts, whose data must never mix.
+            Some(profile) => Path::new(cache_dir)
+                .join("profiles")
+                .join(profile)
+                .display()
+                .to_string(),
+            None => cache_dir.to_owned(),
+        }
+    }
+
+    /// Get a single value from the highest layer that sets it, skipping layers that failed to load.
+    pub fn get_string(&self, key: &str) -> Option<String> {
+        self.layers
+            .iter()
+            .rev()
+            .filter_map(|(_, layer)| layer.as_ref().ok())
+            .find_map(|layer| layer.get_string(key).ok())
+    }
+
+    /// List every value of the effective configuration, along with the layer it comes from.
+    pub fn report(&self) -> anyhow::Result<ConfigurationReport> {
+        let merged = self.merge()?;
+        let mut keys = Vec::new();
+        collect_keys("", &merged.collect()?, &mut keys);
+        // Only the selected profile matters, and its values are listed as coming from the profile.
+        keys.retain(|key| !key.starts_with("profiles."));
+        keys.sort();
+        let mut values = Vec::new();
+        for key in keys {
+            let source = self
+                .layers
+                .iter()
+                .rev()
+                .find(|(_, layer)| {
+                    layer
+                        .as_ref()
+                        .is_ok_and(|l| l.get::<config::Value>(&key).is_ok())
+                })
+                .map_or(ConfigurationSource::Default, |(source, _)| source.clone());
+            // The API token is a secret, which should not end up in terminal scrollback or logs.
+            let value = if key == "api_token" || key.ends_with(".api_token") {
+                serde_json::Value::from("<redacted>")
+            } else {
+                merged.get::<serde_json::Value>(&key)?
+            };
+            values.push(ConfigurationValue {
+                key,
+                value,
+                source: source.to_string(),
+            });
+        }
+        Ok(ConfigurationReport {
+            file: self.file().map(|p| p.display().to_string()),
+            profile: self.profile.clone(),
+            values,
+        })
+    }
+}
+
+/// The built-in defaults of the configuration.
+fn defaults() -> Result<Config, ConfigError> {
+    Config::builder()
+        .set_default("anki_endpoint", "http://localhost:8765")?
+        .set_default("cache_dir", ".cache")?
+        .set_default("cache_backend", "json")?
+        .set_default("cache_compression", "none")?
+        .set_default("kanji.deck_name", "Japanese Kanji")?
+        .set_default("kanji.model_name", "Japanese Kanji")?
+        .set_default("vocabulary.deck_name", "Japanese Vocabulary")?
+        .set_default("vocabulary.model_name", "Japanese Vocabulary")?
+        .build()
+}
+
+fn find_configuration_file(options: &Options) -> Option<PathBuf> {
+    // Explicitly given files are used even when they do not exist, so that loading them reports the mistake.
+    if let Some(path) = &options.config {
+        return Some(path.clone());
+    }
+    if let Some(path) = std::env::var_os("WANIKANJI_CONFIG") {
+        return Some(PathBuf::from(path));
+    }
+    let candidates = [Some(PathBuf::from(WORKING_DIRECTORY_FILE)), user_file()];
+    candidates.into_iter().flatten().find(|path| path.is_file())
+}
+
+/// The environment variables that set configuration values, along with the key they set.
+const ENVIRONMENT_VARIABLES: [(&str, &str); 2] = [
+    ("WANIKANJI_API_TOKEN", "api_token"),
+    ("WANIKANJI_ANKI_ENDPOINT", "anki_endpoint"),
+];
+
+/// The configuration file that commands read instead of `path`, because it comes first in the search order.
+pub fn shadowing_configuration_file(options: &Options, path: &Path) -> Option<PathBuf> {
+    find_configuration_file(options).filter(|found| found != path)
+}
+
+/// The configuration file that `init` writes when no file is given: `$WANIKANJI_CONFIG`, or the file in the
+/// configuration directory of the user.
+pub fn user_configuration_file() -> Option<PathBuf> {
+    std::env::var_os("WANIKANJI_CONFIG")
+        .map(PathBuf::from)
+        .or_else(user_file)
+}
+
+fn user_file() -> Option<PathBuf> {
+    let config_home = std::env::var_os("XDG_CONFIG_HOME")
+        .map(PathBuf::from)
+        .or_else(|| std::env::var_os("HOME").map(|home| Path::new(&home).join(".config")));
+    config_home.map(|c| c.join("wanikanji").join("config.toml"))
+}
+
+/// Read the settings of a profile from the `[profiles.<name>]` table of the configuration file.
+fn profile(
+    name: &str,
+    path: &Path,
+    file: &Result<Config, ConfigError>,
+) -> Result<Config, ConfigError> {
+    // The name becomes a directory in the cache directory, and a dot would make it a nested table.
+    if name.is_empty()
+        || !name
+            .chars()
+            .all(|c| c.is_alphanumeric() || c == '-' || c == '_')
+    {
+        return Err(ConfigError::Message(format!(
+            "profile name '{}' may only contain letters, digits, '-' and '_'",
+            name
+        )));
+    }
+    let Ok(file) = file else {
+        return Err(ConfigError::Message(format!(
+            "profile '{}' cannot be read, because {} is invalid",
+            name,
+            path.display()
+        )));
+    };
+    let profile = file
+        .get::<serde_json::Value>(&format!("profiles.{}", name))
+        .map_err(|_| {
+            ConfigError::Message(format!(
+                "profile '{}' is not defined in {}",
+                name,
+                path.display()
+            ))
+        })?;
+    Config::try_from(&profile)
+}
+
+/// Collect the dotted keys of every value that is not a table.
+fn collect_keys(prefix: &str, table: &config::Map<String, config::Value>, keys: &mut Vec<String>) {
+    for (name, value) in table {
+        let key = if prefix.is_empty() {
+            name.clone()
+        } else {
+            format!("{}.{}", prefix, name)
+        };
+        match &value.kind {
+            config::ValueKind::Table(table) => collect_keys(&key, table, keys),
+            _ => keys.push(key),
+        }
+    }
+}
+
+impl fmt::Display for ConfigurationSource {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ConfigurationSource::Default => write!(f, "default"),
+            ConfigurationSource::File(path) => write!(f, "{}", path.display()),
+            ConfigurationSource::Profile(name) => write!(f, "profile {}", name),
+            ConfigurationSource::Environment(variable) => write!(f, "${}", variable),
+            ConfigurationSource::CommandLine => write!(f, "command line"),
+        }
+    }
+}
+
+impl fmt::Display for ConfigurationReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match &self.file {
+            Some(file) => writeln!(f, "# configuration file: {}", file)?,
+            None => writeln!(f, "# no configuration file, using the built-in defaults")?,
+        }
+        if let Some(profile) = &self.profile {
+            writeln!(
+                f,
+                "# profile: {}, whose cache is stored in profiles/{} inside cache_dir",
+                profile, profile
+            )?;
+        }
+        for value in &self.values {
+            writeln!(f, "{} = {}  # {}", value.key, value.value, value.source)?;
+        }
+        Ok(())
+    }
+}
+
+#[derive(Debug, Deserialize)]
+pub struct Configuration {
+    /// Filled in from `api_token` or `token_file` after the layers are merged.
+    #[serde(skip)]
+    pub api_token: Option<ApiToken>,
+    /// File that contains the API token, relative to the configuration file.
+    pub token_file: Option<PathBuf>,
+    pub anki_endpoint: String,
+    pub cache_dir: String,
+    pub cache_backend: CacheBackend,
+    /// How the JSON cache compresses its files, which the SQLite cache ignores.
+    pub cache_compression: CacheCompression,
+    pub kanji: ConfigurationDeckOptions,
+    pub vocabulary: ConfigurationDeckOptions,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct ConfigurationDeckOptions {
+    pub deck_name: String,
+    pub model_name: String,
+    /// Legacy way of configuring a single card template, superseded by `templates`.
+    pub model_template_name: Option<String>,
+    pub model_template_front: Option<String>,
+    pub model_template_back: Option<String>,
+    /// The card templates of the note type, each of which produces a card for every note.
+    #[serde(default)]
+    pub templates: Vec<ConfigurationTemplateOptions>,
+    /// The stylesheet shared by the card templates of the note type.
+    #[serde(default = "default_css")]
+    pub css: Resource,
+    /// The fields of the note type in order. The first field identifies a note.
+    #[serde(default)]
+    pub fields: Vec<ConfigurationFieldOptions>,
+    /// The number of context sentences in the built-in vocabulary note type, which only applies when no fields are
+    /// configured.
+    pub context_sentences: Option<usize>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct ConfigurationTemplateOptions {
+    pub name: String,
+    pub front: Resource,
+    pub back: Resource,
+}
+
+/// A note field, rendered either from a source expression or from a template.
+#[derive(Debug, Deserialize)]
+pub struct ConfigurationFieldOptions {
+    pub name: String,
+    pub source: Option<FieldSource>,
+    pub template: Option<FieldTemplate>,
+    #[serde(default = "default_field_separator")]
+    pub separator: String,
+    /// Whether a subject that renders an empty value for this field fails to convert.
+    #[serde(default)]
+    pub required: bool,
+}
+
+fn default_css() -> Resource {
+    Resource::builtin("res/anki.css")
+}
+
+fn default_field_separator() -> String {
+    ", ".to_owned()
+}
+
+impl ConfigurationDeckOptions {
+    /// Move the legacy single card template options into the list of templates.
+    fn merge_legacy_template(&mut self) {
+        let name = self.model_template_name.take();
+        let front = self.model_template_front.take();
+        let back = self.model_template_back.take();
+        if let (Some(name), Some(front), Some(back)) = (name, front, back) {
+            self.templates.insert(
+                0,
+                ConfigurationTemplateOptions {
+                    name,
+                    front: Resource::File(front.into()),
+                    back: Resource::File(back.into()),
+                },
+            );
+        }
+    }
+
+    pub fn field_names(&self) -> Vec<String> {
+        self.fields.iter().map(|f| f.name.clone()).collect()
+    }
+
+    /// The name of the field that is used to match notes against existing notes in Anki.
+    pub fn key_field(&self) -> &str {
+        self.fields.first().map_or("", |f| f.name.as_str())
+    }
+}
+
+impl ConfigurationFieldOptions {
+    pub fn new(name: &str, source: &str) -> Self {
+        Self {
+            name: name.to_owned(),
+            source: Some(FieldSource::parse(source).expect("built-in field source must be valid")),
+            template: None,
+            separator: default_field_separator(),
+            required: false,
+        }
+    }
+
+    pub fn from_template(name: &str, template: &str) -> Self {
+        Self {
+            name: name.to_owned(),
+            source: None,
+            template: Some(
+                FieldTemplate::parse(template).expect("built-in template must be valid"),
+            ),
+            separator: default_field_separator(),
+            required: false,
+        }
+    }
+
+    pub fn required(mut self) -> Self {
+        self.required = true;
+        self
+    }
+
+    /// Render the value of this field for a subject.
+    pub fn render(&self, subject: &serde_json::Value) -> Result<String, TemplateError> {
+        match (&self.source, &self.template) {
+            (Some(source), _) => Ok(source.evaluate(subject).join(&self.separator)),
+            (None, Some(template)) => template.render(subject),
+            (None, None) => Ok(String::new()),
+        }
+    }
+}
+
+impl Configuration {
+    /// Resolve the relative paths of the configured resources against the directory of the configuration file.
+    fn resolve_paths(&mut self, base: &Path) {
+        for deck in [&mut self.kanji, &mut self.vocabulary] {
+            deck.css.resolve(base);
+            for template in &mut deck.templates {
+                template.front.resolve(base);
+                template.back.resolve(base);
+            }
+        }
+    }
+
+    /// Check that the configuration is usable before running any command.
+    pub fn validate(&self) -> anyhow::Result<()> {
+        let decks = [
+            ("kanji", &self.kanji, &ApiKanjiMessage::PROPERTIES[..]),
+            (
+                "vocabulary",
+                &self.vocabulary,
+                &ApiVocabularyMessage::PROPERTIES[..],
+            ),
+        ];
+        for (deck, options, properties) in decks {
+            let variables = [&ApiSubjectMessage::PROPERTIES[..], properties].concat();
+            if options.templates.is_empty() {
+                anyhow::bail!("{} needs at least one card template", deck);
+            }
+            for (i, field) in options.fields.iter().enumerate() {
+                if options.fields[..i].iter().any(|f| f.name == field.name) {
+                    anyhow::bail!(
+                        "field '{}' of {} is declared more than once",
+                        field.name,
+                        deck
+                    );
+                }
+                match (&field.source, &field.template) {
+                    (Some(_), Some(_)) => anyhow::bail!(
+                        "field '{}' of {} has both a source and a template, only one is allowed",
+                        field.name,
+                        deck
+                    ),
+                    (None, None) => anyhow::bail!(
+                        "field '{}' of {} needs either a source or a template",
+                        field.name,
+                        deck
+                    ),
+                    (None, Some(template)) => template
+                        .check_variables(&variables)
+                        .with_context(|| format!("field '{}' of {}", field.name, deck))?,
+                    _ => {}
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use clap::Parser;
+
+    const FILE: &str = r#"
+api_token = "file token"
+cache_dir = "/var/cache/wanikanji"
+
+[profiles.alice]
+token_file = "alice-token"
+"#;
+
+    /// A configuration file with the given contents in a directory unique to the test, next to a token file for the
+    /// `alice` profile.
+    fn configuration_file(test: &str, contents: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!(
+            "wanikanji-configuration-{}-{}",
+            test,
+            std::process::id()
+        ));
+        std::fs::create_dir_all(&dir).unwrap();
+        std::fs::write(dir.join("alice-token"), "profile token\n").unwrap();
+        let path = dir.join("wanikanji.toml");
+        std::fs::write(&path, contents).unwrap();
+        path
+    }
+
+    fn options(args: &[&str]) -> Options {
+        let args = ["wanikanji"].iter().chain(args).chain(&["status"]);
+        Options::parse_from(args)
+    }
+
+    fn token(layers: &ConfigurationLayers) -> Option<String> {
+        let token = layers.api_token().unwrap();
+        token.map(|token| token.expose().to_owned())
+    }
+
+    #[test]
+    fn file_api_token_is_used_without_a_profile() {
+        let path = configuration_file("file-token", FILE);
+        let layers = ConfigurationLayers::collect(&options(&[]), Some(path), &[], None);
+        assert_eq!(token(&layers).as_deref(), Some("file token"));
+    }
+
+    #[test]
+    fn profile_token_file_overrides_file_api_token() {
+        let path = configuration_file("profile-token", FILE);
+        let options = options(&["--profile", "alice"]);
+        let layers = ConfigurationLayers::collect(&options, Some(path), &[], None);
+        assert_eq!(token(&layers).as_deref(), Some("profile token"));
+    }
+
+    #[test]
+    fn environment_token_overrides_file_and_profile() {
+        let path = configuration_file("environment-token", FILE);
+        let options = options(&["--profile", "alice"]);
+        let environment = [("WANIKANJI_API_TOKEN", "api_token", "env token".to_owned())];
+        let layers = ConfigurationLayers::collect(&options, Some(path), &environment, None);
+        assert_eq!(token(&layers).as_deref(), Some("env token"));
+    }
+
+    #[test]
+    fn command_line_token_overrides_everything() {
+        let path = configuration_file("command-line-token", FILE);
+        let environment = [("WANIKANJI_API_TOKEN", "api_token", "env token".to_owned())];
+
+        let command_line = options(&["--profile", "alice", "--api-token", "cli token"]);
+        let layers =
+            ConfigurationLayers::collect(&command_line, Some(path.clone()), &environment, None);
+        assert_eq!(token(&layers).as_deref(), Some("cli token"));
+
+        let stdin = options(&["--profile", "alice", "--api-token-stdin"]);
+        let stdin_token = Some(Ok(ApiToken::new("stdin token")));
+        let layers = ConfigurationLayers::collect(&stdin, Some(path), &environment, stdin_token);
+        assert_eq!(token(&layers).as_deref(), Some("stdin token"));
+    }
+
+    #[test]
+    fn profile_data_is_cached_in_its_own_directory() {
+        let path = configuration_file("profile-cache-dir", FILE);
+        let options = options(&["--profile", "alice"]);
+        let layers = ConfigurationLayers::collect(&options, Some(path), &[], None);
+        assert_eq!(
+            layers.cache_dir().as_deref(),
+            Some("/var/cache/wanikanji/profiles/alice")
+        );
+        assert_eq!(
+            layers.load().unwrap().cache_dir,
+            "/var/cache/wanikanji/profiles/alice"
+        );
+    }
+
+    #[test]
+    fn cache_dir_is_used_as_is_without_a_profile() {
+        let path = configuration_file("cache-dir", FILE);
+        let layers = ConfigurationLayers::collect(&options(&[]), Some(path), &[], None);
+        assert_eq!(layers.cache_dir().as_deref(), Some("/var/cache/wanikanji"));
+        assert_eq!(layers.load().unwrap().cache_dir, "/var/cache/wanikanji");
+    }
+}
@@ -0,0 +1,284 @@
+use crate::anki_connect::client::{AnkiClient, AnkiError};
+use crate::anki_connect::rpc::version::VersionInput;
+use crate::configuration::{Configuration, ConfigurationLayers};
+use crate::query::{QueryClient, QueryError};
+use crate::resources::Resource;
+use crate::token::ApiToken;
+use crate::Options;
+use reqwest::StatusCode;
+use serde::Serialize;
+use std::fmt;
+use std::path::Path;
+
+/// The oldest version of the Anki Connect API that supports every request wanikanji sends.
+const REQUIRED_ANKI_CONNECT_VERSION: u16 = 6;
+
+/// The outcome of checking everything wanikanji depends on.
+#[derive(Debug, Serialize)]
+pub struct DoctorReport {
+    pub checks: Vec<Check>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct Check {
+    pub name: &'static str,
+    pub ok: bool,
+    pub message: String,
+    /// What the user can do to make the check pass.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub fix: Option<String>,
+}
+
+impl Check {
+    fn pass(name: &'static str, message: String) -> Self {
+        Self {
+            name,
+            ok: true,
+            message,
+            fix: None,
+        }
+    }
+
+    fn fail(name: &'static str, message: String, fix: String) -> Self {
+        Self {
+            name,
+            ok: false,
+            message,
+            fix: Some(fix),
+        }
+    }
+}
+
+impl DoctorReport {
+    /// Run every check against the given options and the configuration they lead to.
+    pub async fn diagnose(options: &Options) -> Self {
+        let mut checks = Vec::new();
+        let layers = ConfigurationLayers::discover(options);
+        let configuration = match layers.load() {
+            Ok(configuration) => {
+                let message = match layers.file() {
+                    Some(file) => format!("{} is valid", file.display()),
+                    None => "no configuration file found, using the built-in defaults".to_owned(),
+                };
+                checks.push(Check::pass("configuration", message));
+                Some(configuration)
+            }
+            Err(e) => {
+                checks.push(Check::fail(
+                    "configuration",
+                    format!("{:#}", e),
+                    "fix the configuration file, see the README for the available options"
+                        .to_owned(),
+                ));
+                None
+            }
+        };
+        if let Some(configuration) = &configuration {
+            checks.push(check_resources(configuration));
+        }
+        // The remaining checks use whatever values could be read, even when the configuration as a whole is invalid.
+        let cache_dir = layers.cache_dir().unwrap_or_default();
+        let anki_endpoint = layers.get_string("anki_endpoint").unwrap_or_default();
+        checks.push(check_cache_dir(&cache_dir).await);
+        checks.push(check_anki_connect(&anki_endpoint).await);
+        match layers.api_token() {
+            Ok(api_token) => checks.push(check_api_token(api_token.as_ref()).await),
+            Err(e) => checks.push(Check::fail(
+                "api-token",
+                e.to_string(),
+                "make sure token_file points at a readable file that contains the token".to_owned(),
+            )),
+        }
+        Self { checks }
+    }
+
+    pub fn failures(&self) -> usize {
+        self.checks.iter().filter(|c| !c.ok).count()
+    }
+}
+
+/// Check that the configured card templates and stylesheets exist.
+fn check_resources(configuration: &Configuration) -> Check {
+    let mut missing = Vec::new();
+    for deck in [&configuration.kanji, &configuration.vocabulary] {
+        let templates = deck.templates.iter().flat_map(|t| [&t.front, &t.back]);
+        for resource in templates.chain([&deck.css]) {
+            if let Resource::File(path) = resource {
+                if !path.is_file() {
+                    missing.push(path.display().to_string());
+                }
+            }
+        }
+    }
+    if missing.is_empty() {
+        return Check::pass(
+            "resources",
+            "card templates and stylesheets exist".to_owned(),
+        );
+    }
+    Check::fail(
+        "resources",
+        format!("missing files: {}", missing.join(", ")),
+        "point the templates and css in the configuration file at existing files, relative to the configuration file, or run export-resources to start from the built-in ones".to_owned(),
+    )
+}
+
+/// Check that the cache directory exists and that files can be written to it.
+async fn check_cache_dir(cache_dir: &str) -> Check {
+    if tokio::fs::metadata(cache_dir).await.is_err() {
+        return Check::fail(
+            "cache-directory",
+            format!("cache directory '{}' does not exist", cache_dir),
+            format!(
+                "create it with `mkdir -p {}`, or pass another directory with --cache-dir",
+                cache_dir
+            ),
+        );
+    }
+    let probe = Path::new(cache_dir).join(".wanikanji-doctor");
+    let written = tokio::fs::write(&probe, b"").await;
+    let _ = tokio::fs::remove_file(&probe).await;
+    match written {
+        Ok(()) => Check::pass(
+            "cache-directory",
+            format!("cache directory '{}' is writable", cache_dir),
+        ),
+        Err(e) => Check::fail(
+            "cache-directory",
+            format!("cannot write to cache directory '{}': {}", cache_dir, e),
+            "make the directory writable for your user, or pass another directory with --cache-dir"
+                .to_owned(),
+        ),
+    }
+}
+
+/// Check that Anki Connect is reachable, and that it is recent enough.
+pub async fn check_anki_connect(endpoint: &str) -> Check {
+    let anki_client = AnkiClient::from_endpoint(endpoint);
+    match anki_client.version().await {
+        Ok(version) if version >= REQUIRED_ANKI_CONNECT_VERSION => Check::pass(
+            "anki-connect",
+            format!("Anki Connect at {} speaks version {}", endpoint, version),
+        ),
+        Ok(version) => Check::fail(
+            "anki-connect",
+            format!(
+                "Anki Connect at {} speaks version {}, but version {} is required",
+                endpoint, version, REQUIRED_ANKI_CONNECT_VERSION
+            ),
+            "update the AnkiConnect add-on from Anki's add-ons menu".to_owned(),
+        ),
+        Err(AnkiError::HttpError(e)) if e.is_connect() => Check::fail(
+            "anki-connect",
+            format!("cannot connect to Anki Connect at {}", endpoint),
+            "start Anki with the AnkiConnect add-on (https://ankiweb.net/shared/info/2055492159) installed, or pass the right address with --anki-endpoint".to_owned(),
+        ),
+        Err(e) => Check::fail(
+            "anki-connect",
+            format!("unexpected response from {}: {}", endpoint, e),
+            "check that --anki-endpoint points at Anki Connect".to_owned(),
+        ),
+    }
+}
+
+/// Check that the WaniKani API token is accepted.
+pub async fn check_api_token(api_token: Option<&ApiToken>) -> Check {
+    let Some(api_token) = api_token else {
+        return Check::fail(
+            "api-token",
+            "no WaniKani API token was given".to_owned(),
+            "create a token at https://www.wanikani.com/settings/personal_access_tokens and pass it with $WANIKANJI_API_TOKEN, --api-token-stdin or token_file in the configuration"
+                .to_owned(),
+        );
+    };
+    let wanikani_client = QueryClient::from_token(Some(api_token));
+    match wanikani_client.get_user().await {
+        Ok(user) => Check::pass(
+            "api-token",
+            format!(
+                "WaniKani API token belongs to {} (level {})",
+                user.username, user.level
+            ),
+        ),
+        Err(QueryError::QueryFailed(StatusCode::UNAUTHORIZED)) => Check::fail(
+            "api-token",
+            "WaniKani rejected the API token".to_owned(),
+            "check the token for typos, or create a new one at https://www.wanikani.com/settings/personal_access_tokens".to_owned(),
+        ),
+        Err(e) => Check::fail(
+            "api-token",
+            format!("could not verify the API token: {}", e),
+            "check your internet connection and try again".to_owned(),
+        ),
+    }
+}
+
+impl AnkiClient<'_> {
+    pub async fn version(&self) -> Result<u16, AnkiError> {
+        self.send(VersionInput {}).await
+    }
+}
+
+impl fmt::Display for DoctorReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for check in &self.checks {
+            write!(f, "{}", check)?;
+        }
+        Ok(())
+    }
+}
+
+impl fmt::Display for Check {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let status = if self.ok { "ok" } else { "FAIL" };
+        writeln!(f, "[{:<4}] {}: {}", status, self.name, self.message)?;
+        if let Some(fix) = &self.fix {
+            writeln!(f, "       fix: {}", fix)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use clap::Parser;
+
+    #[tokio::test]
+    async fn cache_directory_check_uses_the_profile_directory() {
+        let dir = std::env::temp_dir().join(format!("wanikanji-doctor-{}", std::process::id()));
+        let cache_dir = dir.join("cache");
+        std::fs::create_dir_all(cache_dir.join("profiles").join("alice")).unwrap();
+        let path = dir.join("wanikanji.toml");
+        let contents = format!(
+            "cache_dir = {}\n\n[profiles.alice]\nanki_endpoint = \"http://127.0.0.1:1\"\n",
+            serde_json::to_string(&cache_dir.display().to_string()).unwrap()
+        );
+        std::fs::write(&path, contents).unwrap();
+        let options = Options::parse_from([
+            "wanikanji",
+            "--config",
+            &path.display().to_string(),
+            "--profile",
+            "alice",
+            "doctor",
+        ]);
+
+        let report = DoctorReport::diagnose(&options).await;
+
+        let check = report
+            .checks
+            .iter()
+            .find(|c| c.name == "cache-directory")
+            .unwrap();
+        assert!(check.ok, "{}", check.message);
+        assert!(
+            check
+                .message
+                .contains(&format!("profiles{}alice", std::path::MAIN_SEPARATOR)),
+            "{}",
+            check.message
+        );
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+}
@@ -0,0 +1,306 @@
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::fmt;
+use thiserror::Error;
+
+#[derive(Debug, Error)]
+pub enum FieldSourceError {
+    #[error("empty path segment in field source '{0}'")]
+    EmptySegment(String),
+    #[error("unterminated selector in field source '{0}'")]
+    UnterminatedSelector(String),
+    #[error("invalid selector '{1}' in field source '{0}'")]
+    InvalidSelector(String, String),
+}
+
+/// A path expression that selects values from a WaniKani subject.
+///
+/// An expression is a list of object keys separated by dots. Each key may be followed by selectors in square brackets
+/// which apply to the elements of an array:
+///
+/// - `[0]` selects the element at the given index
+/// - `[primary]` and `[!primary]` keep elements where the given key is true or false respectively
+/// - `[type=onyomi]` keeps elements where the given key equals the given value
+///
+/// For example `meanings[primary].meaning` selects the primary meaning of a subject, and `context_sentences[0].ja`
+/// selects the Japanese text of the first context sentence.
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[serde(try_from = "String", into = "String")]
+pub struct FieldSource {
+    expression: String,
+    segments: Vec<Segment>,
+}
+
+#[derive(Debug, Clone)]
+struct Segment {
+    key: String,
+    selectors: Vec<Selector>,
+}
+
+#[derive(Debug, Clone)]
+enum Selector {
+    Index(usize),
+    Flag(String, bool),
+    Equals(String, String),
+}
+
+impl FieldSource {
+    pub fn parse(expression: &str) -> Result<Self, FieldSourceError> {
+        let segments = expression
+            .split('.')
+            .map(|segment| Segment::parse(expression, segment))
+            .collect::<Result<Vec<_>, _>>()?;
+        Ok(Self {
+            expression: expression.to_owned(),
+            segments,
+        })
+    }
+
+    /// Evaluate the expression against a value, returning every scalar it selects as a string.
+    pub fn evaluate(&self, root: &Value) -> Vec<String> {
+        let mut values = vec![root];
+        for segment in &self.segments {
+            values = values
+                .into_iter()
+                .filter_map(|v| v.get(&segment.key))
+                .flat_map(|v| segment.select(v))
+                .collect();
+        }
+        values
+            .into_iter()
+            .filter_map(|v| match v {
+                Value::String(s) => Some(s.clone()),
+                Value::Number(n) => Some(n.to_string()),
+                Value::Bool(b) => Some(b.to_string()),
+                _ => None,
+            })
+            .collect()
+    }
+}
+
+impl Segment {
+    fn parse(expression: &str, segment: &str) -> Result<Self, FieldSourceError> {
+        let (key, mut rest) = segment.split_at(segment.find('[').unwrap_or(segment.len()));
+        if key.is_empty() {
+            return Err(FieldSourceError::EmptySegment(expression.to_owned()));
+        }
+        let mut selectors = Vec::new();
+        while !rest.is_empty() {
+            let Some(end) = rest.find(']').filter(|_| rest.starts_with('[')) else {
+                return Err(FieldSourceError::UnterminatedSelector(
+                    expression.to_owned(),
+                ));
+            };
+            selectors.push(Selector::parse(expression, &rest[1..end])?);
+            rest = &rest[end + 1..];
+        }
+        Ok(Self {
+            key: key.to_owned(),
+            selectors,
+        })
+    }
+
+    /// Apply the selectors of this segment to a value. Arrays are flattened into their elements.
+    fn select<'v>(&self, value: &'v Value) -> Vec<&'v Value> {
+        let Value::Array(elements) = value else {
+            return if self.selectors.is_empty() {
+                vec![value]
+            } else {
+                vec![]
+            };
+        };
+        let mut elements = elements.iter().collect::<Vec<_>>();
+        for selector in &self.selectors {
+            elements = match selector {
+                Selector::Index(i) => elements.get(*i).into_iter().copied().collect(),
+                Selector::Flag(key, expected) => elements
+                    .into_iter()
+                    .filter(|e| e.get(key).and_then(Value::as_bool) == Some(*expected))
+                    .collect(),
+                Selector::Equals(key, expected) => elements
+                    .into_iter()
+                    .filter(|e| match e.get(key) {
+                        Some(Value::String(s)) => s == expected,
+                        Some(Value::Null) | None => false,
+                        Some(v) => &v.to_string() == expected,
+                    })
+                    .collect(),
+            };
+        }
+        elements
+    }
+}
+
+impl Selector {
+    fn parse(expression: &str, selector: &str) -> Result<Self, FieldSourceError> {
+        let invalid =
+            || FieldSourceError::InvalidSelector(expression.to_owned(), selector.to_owned());
+        if let Ok(index) = selector.parse::<usize>() {
+            return Ok(Selector::Index(index));
+        }
+        if let Some((key, value)) = selector.split_once('=') {
+            if key.is_empty() {
+                return Err(invalid());
+            }
+            return Ok(Selector::Equals(key.to_owned(), value.to_owned()));
+        }
+        let (key, expected) = match selector.strip_prefix('!') {
+            Some(key) => (key, false),
+            None => (selector, true),
+        };
+        if key.is_empty() {
+            return Err(invalid());
+        }
+        Ok(Selector::Flag(key.to_owned(), expected))
+    }
+}
+
+impl TryFrom<String> for FieldSource {
+    type Error = FieldSourceError;
+
+    fn try_from(value: String) -> Result<Self, Self::Error> {
+        Self::parse(&value)
+    }
+}
+
+impl From<FieldSource> for String {
+    fn from(value: FieldSource) -> Self {
+        value.expression
+    }
+}
+
+impl fmt::Display for FieldSource {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.expression)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn subject() -> Value {
+        json!({
+            "characters": "上",
+            "level": 1,
+            "meanings": [
+                { "meaning": "Above", "primary": true },
+                { "meaning": "Up", "primary": false },
+                { "meaning": "Over", "primary": false },
+            ],
+            "readings": [
+                { "reading": "じょう", "type": "onyomi", "primary": true },
+                { "reading": "うえ", "type": "kunyomi", "primary": false },
+            ],
+        })
+    }
+
+    fn evaluate(expression: &str) -> Vec<String> {
+        FieldSource::parse(expression).unwrap().evaluate(&subject())
+    }
+
+    fn error(expression: &str) -> String {
+        FieldSource::parse(expression).unwrap_err().to_string()
+    }
+
+    #[test]
+    fn key_selects_a_scalar() {
+        assert_eq!(evaluate("characters"), ["上"]);
+        assert_eq!(evaluate("level"), ["1"]);
+        assert!(evaluate("missing").is_empty());
+    }
+
+    #[test]
+    fn arrays_are_flattened() {
+        assert_eq!(evaluate("meanings.meaning"), ["Above", "Up", "Over"]);
+    }
+
+    #[test]
+    fn index_selects_an_element() {
+        assert_eq!(evaluate("meanings[0].meaning"), ["Above"]);
+        assert_eq!(evaluate("meanings[2].meaning"), ["Over"]);
+        assert!(evaluate("meanings[3].meaning").is_empty());
+    }
+
+    #[test]
+    fn flag_keeps_elements_where_the_key_is_true() {
+        assert_eq!(evaluate("meanings[primary].meaning"), ["Above"]);
+        assert_eq!(evaluate("meanings[!primary].meaning"), ["Up", "Over"]);
+    }
+
+    #[test]
+    fn equality_keeps_elements_with_the_value() {
+        assert_eq!(evaluate("readings[type=kunyomi].reading"), ["うえ"]);
+        assert_eq!(evaluate("readings[primary=true].reading"), ["じょう"]);
+        assert!(evaluate("readings[type=nanori].reading").is_empty());
+    }
+
+    #[test]
+    fn selectors_apply_in_order() {
+        assert_eq!(evaluate("meanings[!primary][1].meaning"), ["Over"]);
+    }
+
+    #[test]
+    fn selectors_on_a_scalar_select_nothing() {
+        assert!(evaluate("characters[0]").is_empty());
+    }
+
+    #[test]
+    fn the_expression_is_kept_for_display() {
+        let source = FieldSource::parse("meanings[primary].meaning").unwrap();
+        assert_eq!(source.to_string(), "meanings[primary].meaning");
+        assert_eq!(String::from(source), "meanings[primary].meaning");
+    }
+
+    #[test]
+    fn empty_segments_are_rejected() {
+        assert_eq!(error(""), "empty path segment in field source ''");
+        assert_eq!(
+            error("meanings..meaning"),
+            "empty path segment in field source 'meanings..meaning'"
+        );
+        assert_eq!(
+            error("[0].meaning"),
+            "empty path segment in field source '[0].meaning'"
+        );
+    }
+
+    #[test]
+    fn unterminated_selectors_are_rejected() {
+        assert_eq!(
+            error("meanings[0"),
+            "unterminated selector in field source 'meanings[0'"
+        );
+        assert_eq!(
+            error("meanings[0]x"),
+            "unterminated selector in field source 'meanings[0]x'"
+        );
+    }
+
+    #[test]
+    fn invalid_selectors_are_rejected() {
+        assert_eq!(
+            error("meanings[]"),
+            "invalid selector '' in field source 'meanings[]'"
+        );
+        assert_eq!(
+            error("meanings[!]"),
+            "invalid selector '!' in field source 'meanings[!]'"
+        );
+        assert_eq!(
+            error("readings[=onyomi]"),
+            "invalid selector '=onyomi' in field source 'readings[=onyomi]'"
+        );
+    }
+
+    #[test]
+    fn configuration_values_are_parsed() {
+        let source = serde_json::from_value::<FieldSource>(json!("meanings[0].meaning")).unwrap();
+        assert_eq!(source.evaluate(&subject()), ["Above"]);
+        let error = serde_json::from_value::<FieldSource>(json!("meanings[")).unwrap_err();
+        assert!(error
+            .to_string()
+            .contains("unterminated selector in field source 'meanings['"));
+    }
+}
@@ -0,0 +1,380 @@
+use crate::kanji::ApiKanjiMessage;
+use crate::vocabulary::ApiVocabularyMessage;
+use std::collections::HashMap;
+
+/// Produces Anki furigana markup such as `食[た]べ 物[もの]` from the readings of WaniKani subjects.
+///
+/// Japanese text is annotated on a best-effort basis by matching the longest known vocabulary at each position. To
+/// also cover conjugated words, the stem of vocabulary ending in okurigana (such as `食` for `食べる`) is matched as
+/// well, unless different vocabulary disagree on its reading.
+#[derive(Debug, Default)]
+pub struct FuriganaDictionary {
+    /// The known readings of each kanji, which are used to split the reading of a run of kanji.
+    kanji: HashMap<char, Vec<String>>,
+    words: HashMap<String, String>,
+    stems: HashMap<String, Option<String>>,
+    longest: usize,
+}
+
+/// A run of characters that are either all kanji or all kana.
+#[derive(Debug, PartialEq)]
+struct Segment {
+    text: String,
+    is_kanji: bool,
+}
+
+impl FuriganaDictionary {
+    pub fn new(vocabulary: &[ApiVocabularyMessage], kanji: &[ApiKanjiMessage]) -> Self {
+        let mut dictionary = Self::default();
+        for kanji in kanji {
+            let Some(c) = kanji
+                .subject
+                .characters
+                .as_ref()
+                .and_then(|c| c.chars().next())
+            else {
+                continue;
+            };
+            let readings = kanji
+                .readings
+                .iter()
+                .filter(|r| !r.reading.is_empty())
+                .map(|r| to_hiragana(&r.reading));
+            dictionary.kanji.entry(c).or_default().extend(readings);
+        }
+        for vocabulary in vocabulary {
+            let Some(characters) = &vocabulary.subject.characters else {
+                continue;
+            };
+            let Some(reading) = vocabulary.readings.iter().find(|r| r.primary) else {
+                continue;
+            };
+            dictionary.insert(characters, &reading.reading);
+        }
+        dictionary
+    }
+
+    fn insert(&mut self, characters: &str, reading: &str) {
+        let segments = segment(characters);
+        if !segments.iter().any(|s| s.is_kanji) {
+            return;
+        }
+        let Some(readings) = align(&segments, &to_hiragana(reading)) else {
+            return;
+        };
+        let annotation = self.format(&segments, &readings);
+        self.words.insert(characters.to_owned(), annotation);
+        self.longest = self.longest.max(characters.chars().count());
+
+        // Index the stem of words ending in okurigana, so that conjugated forms are found too.
+        if let Some(last) = segments
+            .last()
+            .filter(|s| !s.is_kanji && segments.len() > 1)
+        {
+            let stem = &characters[..characters.len() - last.text.len()];
+            let n = segments.len() - 1;
+            let annotation = self.format(&segments[..n], &readings[..n]);
+            self.stems
+                .entry(stem.to_owned())
+                .and_modify(|existing| {
+                    if existing.as_ref() != Some(&annotation) {
+                        *existing = None;
+                    }
+                })
+                .or_insert(Some(annotation));
+        }
+    }
+
+    /// Annotate a single word with its reading.
+    ///
+    /// If the reading cannot be aligned with the kana in the word, the whole word is annotated instead.
+    pub fn annotate_word(&self, characters: &str, reading: &str) -> String {
+        let segments = segment(characters);
+        if !segments.iter().any(|s| s.is_kanji) {
+            return characters.to_owned();
+        }
+        match align(&segments, &to_hiragana(reading)) {
+            Some(readings) => self.format(&segments, &readings).trim_start().to_owned(),
+            None => format!("{}[{}]", characters, reading),
+        }
+    }
+
+    /// Annotate the words in a sentence that are found in the dictionary.
+    pub fn annotate_sentence(&self, sentence: &str) -> String {
+        let chars = sentence.chars().collect::<Vec<_>>();
+        let mut output = String::new();
+        let mut i = 0;
+        while i < chars.len() {
+            let longest = self.longest.min(chars.len() - i);
+            let found = (1..=longest).rev().find_map(|n| {
+                let candidate = chars[i..i + n].iter().collect::<String>();
+                // A stem is only a match when okurigana follows it, so that `食` of `食べる` does not match `食堂`.
+                let stem = || {
+                    chars
+                        .get(i + n)
+                        .filter(|c| is_hiragana(**c))
+                        .and_then(|_| self.stems.get(&candidate))
+                        .and_then(Option::as_ref)
+                };
+                self.words
+                    .get(&candidate)
+                    .or_else(stem)
+                    .map(|annotation| (n, annotation))
+            });
+            match found {
+                Some((n, annotation)) => {
+                    output.push_str(annotation);
+                    i += n;
+                }
+                None => {
+                    output.push(chars[i]);
+                    i += 1;
+                }
+            }
+        }
+        output
+    }
+
+    /// Render segments with their aligned readings. Every kanji run is preceded by a space, which Anki uses to find
+    /// the start of the text the reading belongs to.
+    fn format(&self, segments: &[Segment], readings: &[String]) -> String {
+        let mut output = String::new();
+        for (segment, reading) in segments.iter().zip(readings) {
+            if !segment.is_kanji {
+                output.push_str(&segment.text);
+                continue;
+            }
+            let kanji = segment.text.chars().collect::<Vec<_>>();
+            match self.split_run(&kanji, reading).filter(|_| kanji.len() > 1) {
+                Some(parts) => {
+                    for (c, part) in kanji.iter().zip(parts) {
+                        output.push_str(&format!(" {}[{}]", c, part));
+                    }
+                }
+                None => output.push_str(&format!(" {}[{}]", segment.text, reading)),
+            }
+        }
+        output
+    }
+
+    /// Split the reading of a run of kanji into the reading of each kanji, using their known readings.
+    fn split_run<'r>(&self, kanji: &[char], reading: &'r str) -> Option<Vec<&'r str>> {
+        let Some((first, rest)) = kanji.split_first() else {
+            return reading.is_empty().then(Vec::new);
+        };
+        for candidate in self.kanji.get(first).into_iter().flatten() {
+            let Some(remaining) = reading.strip_prefix(candidate.as_str()) else {
+                continue;
+            };
+            if let Some(mut parts) = self.split_run(rest, remaining) {
+                parts.insert(0, &reading[..candidate.len()]);
+                return Some(parts);
+            }
+        }
+        None
+    }
+}
+
+/// Find the reading of each kanji segment, given that kana segments must appear literally in the reading.
+fn align(segments: &[Segment], reading: &str) -> Option<Vec<String>> {
+    let Some((first, rest)) = segments.split_first() else {
+        return reading.is_empty().then(Vec::new);
+    };
+    if !first.is_kanji {
+        let remaining = reading.strip_prefix(to_hiragana(&first.text).as_str())?;
+        let mut readings = align(rest, remaining)?;
+        readings.insert(0, first.text.clone());
+        return Some(readings);
+    }
+    if reading.is_empty() {
+        return None;
+    }
+    for (end, _) in reading.char_indices().skip(1).chain([(reading.len(), ' ')]) {
+        if let Some(mut readings) = align(rest, &reading[end..]) {
+            readings.insert(0, reading[..end].to_owned());
+            return Some(readings);
+        }
+    }
+    None
+}
+
+fn segment(text: &str) -> Vec<Segment> {
+    let mut segments: Vec<Segment> = Vec::new();
+    for c in text.chars() {
+        let is_kanji = is_kanji(c);
+        match segments.last_mut() {
+            Some(last) if last.is_kanji == is_kanji => last.text.push(c),
+            _ => segments.push(Segment {
+                text: c.to_string(),
+                is_kanji,
+            }),
+        }
+    }
+    segments
+}
+
+fn is_kanji(c: char) -> bool {
+    matches!(c, '\u{4E00}'..='\u{9FFF}' | '\u{3400}'..='\u{4DBF}' | '々' | '〆' | 'ヶ')
+}
+
+fn is_hiragana(c: char) -> bool {
+    matches!(c, '\u{3041}'..='\u{309F}')
+}
+
+fn to_hiragana(text: &str) -> String {
+    text.chars()
+        .map(|c| match c {
+            '\u{30A1}'..='\u{30F6}' => char::from_u32(c as u32 - 0x60).unwrap_or(c),
+            _ => c,
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::kanji::ApiKanjiReadingMessage;
+    use crate::query::ApiSubjectMessage;
+    use crate::vocabulary::ApiVocabularyReadingMessage;
+
+    fn subject(characters: &str) -> ApiSubjectMessage {
+        ApiSubjectMessage {
+            characters: Some(characters.to_owned()),
+            ..Default::default()
+        }
+    }
+
+    fn vocabulary(characters: &str, reading: &str) -> ApiVocabularyMessage {
+        ApiVocabularyMessage {
+            subject: subject(characters),
+            readings: vec![ApiVocabularyReadingMessage {
+                accepted_answer: true,
+                primary: true,
+                reading: reading.to_owned(),
+            }],
+            ..Default::default()
+        }
+    }
+
+    fn kanji(characters: &str, readings: &[&str]) -> ApiKanjiMessage {
+        ApiKanjiMessage {
+            subject: subject(characters),
+            readings: readings
+                .iter()
+                .map(|reading| ApiKanjiReadingMessage {
+                    reading: reading.to_string(),
+                    primary: true,
+                    accepted_answer: true,
+                    r#type: "onyomi".to_owned(),
+                })
+                .collect(),
+            ..Default::default()
+        }
+    }
+
+    fn with_vocabulary(vocabulary_readings: &[(&str, &str)]) -> FuriganaDictionary {
+        let vocabulary = vocabulary_readings
+            .iter()
+            .map(|(characters, reading)| vocabulary(characters, reading))
+            .collect::<Vec<_>>();
+        FuriganaDictionary::new(&vocabulary, &[])
+    }
+
+    #[test]
+    fn okurigana_is_left_out_of_the_reading() {
+        let dictionary = FuriganaDictionary::default();
+        assert_eq!(dictionary.annotate_word("食べる", "たべる"), "食[た]べる");
+        assert_eq!(
+            dictionary.annotate_word("お見舞い", "おみまい"),
+            "お 見舞[みま]い"
+        );
+    }
+
+    #[test]
+    fn kana_between_kanji_splits_the_reading() {
+        let dictionary = FuriganaDictionary::default();
+        assert_eq!(
+            dictionary.annotate_word("食べ物", "たべもの"),
+            "食[た]べ 物[もの]"
+        );
+    }
+
+    #[test]
+    fn katakana_matches_the_hiragana_of_the_reading() {
+        let dictionary = FuriganaDictionary::default();
+        assert_eq!(
+            dictionary.annotate_word("食パン", "しょくパン"),
+            "食[しょく]パン"
+        );
+    }
+
+    #[test]
+    fn kanji_runs_are_split_by_known_kanji_readings() {
+        let kanji = [kanji("学", &["がく", "がっ"]), kanji("校", &["こう"])];
+        let dictionary = FuriganaDictionary::new(&[], &kanji);
+        assert_eq!(
+            dictionary.annotate_word("学校", "がっこう"),
+            "学[がっ] 校[こう]"
+        );
+    }
+
+    #[test]
+    fn kanji_runs_without_known_readings_are_annotated_as_a_whole() {
+        let kanji = [kanji("学", &["がく"])];
+        let dictionary = FuriganaDictionary::new(&[], &kanji);
+        assert_eq!(
+            dictionary.annotate_word("学校", "がっこう"),
+            "学校[がっこう]"
+        );
+    }
+
+    #[test]
+    fn words_that_cannot_be_aligned_are_annotated_as_a_whole() {
+        let dictionary = FuriganaDictionary::default();
+        assert_eq!(dictionary.annotate_word("食べる", "のむ"), "食べる[のむ]");
+    }
+
+    #[test]
+    fn words_without_kanji_are_left_as_they_are() {
+        let dictionary = FuriganaDictionary::default();
+        assert_eq!(dictionary.annotate_word("ひらがな", "ひらがな"), "ひらがな");
+    }
+
+    #[test]
+    fn sentences_annotate_the_longest_known_word() {
+        let dictionary = with_vocabulary(&[("日本", "にほん"), ("日本語", "にほんご")]);
+        assert_eq!(
+            dictionary.annotate_sentence("日本語です"),
+            " 日本語[にほんご]です"
+        );
+        assert_eq!(
+            dictionary.annotate_sentence("日本人です"),
+            " 日本[にほん]人です"
+        );
+    }
+
+    #[test]
+    fn stems_match_conjugated_words() {
+        let dictionary = with_vocabulary(&[("食べる", "たべる")]);
+        assert_eq!(dictionary.annotate_sentence("食べた"), " 食[た]べた");
+    }
+
+    #[test]
+    fn stems_only_match_before_hiragana() {
+        let dictionary = with_vocabulary(&[("食べる", "たべる")]);
+        assert_eq!(dictionary.annotate_sentence("食堂で"), "食堂で");
+        assert_eq!(dictionary.annotate_sentence("食"), "食");
+        let dictionary = with_vocabulary(&[("食べる", "たべる"), ("食堂", "しょくどう")]);
+        assert_eq!(
+            dictionary.annotate_sentence("食堂で食べた"),
+            " 食堂[しょくどう]で 食[た]べた"
+        );
+    }
+
+    #[test]
+    fn stems_with_conflicting_readings_are_not_matched() {
+        let dictionary = with_vocabulary(&[("生きる", "いきる"), ("生える", "はえる")]);
+        assert_eq!(dictionary.annotate_sentence("生きた"), "生きた");
+        assert_eq!(dictionary.annotate_sentence("生きる"), " 生[い]きる");
+    }
+}
@@ -0,0 +1,209 @@
+use crate::configuration::{shadowing_configuration_file, user_configuration_file};
+use crate::doctor::{check_anki_connect, check_api_token, Check};
+use crate::token::ApiToken;
+use crate::{InitOptions, Options};
+use anyhow::Context;
+use serde::Serialize;
+use std::fmt;
+use std::io::{BufRead, IsTerminal, Write};
+use std::path::{Path, PathBuf};
+use tokio::io::AsyncWriteExt;
+
+/// What `wanikanji init` set up.
+#[derive(Debug, Serialize)]
+pub struct InitReport {
+    pub configuration_file: String,
+    pub cache_dir: String,
+    pub token_stored: bool,
+    /// A configuration file that commands read instead of the new one, because it comes first in the search order.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub shadowed_by: Option<String>,
+    /// Whether Anki Connect and WaniKani can be reached with the new configuration.
+    pub checks: Vec<Check>,
+}
+
+/// The answers that go into the new configuration file.
+struct InitAnswers {
+    cache_dir: String,
+    anki_endpoint: String,
+    api_token: Option<ApiToken>,
+    kanji_deck: String,
+    vocabulary_deck: String,
+}
+
+impl InitReport {
+    /// Write a configuration file and create the cache directory, asking for the settings on a terminal unless
+    /// `--non-interactive` is given.
+    pub async fn run(options: &Options, init: &InitOptions) -> anyhow::Result<Self> {
+        let path = match &options.config {
+            Some(path) => path.clone(),
+            None => user_configuration_file()
+                .context("cannot find the configuration directory, pass --config")?,
+        };
+        if path.exists() && !init.force {
+            anyhow::bail!(
+                "configuration file {} already exists, pass --force to replace it",
+                path.display()
+            );
+        }
+
+        let mut answers = InitAnswers {
+            cache_dir: options.cache_dir.clone().unwrap_or_else(default_cache_dir),
+            anki_endpoint: options
+                .anki_endpoint
+                .clone()
+                .unwrap_or_else(|| "http://localhost:8765".to_owned()),
+            api_token: options.api_token.as_deref().map(ApiToken::new),
+            kanji_deck: init.kanji_deck.clone(),
+            vocabulary_deck: init.vocabulary_deck.clone(),
+        };
+        if options.api_token_stdin {
+            answers.api_token = Some(ApiToken::from_stdin()?);
+        }
+        let interactive = !init.non_interactive && std::io::stdin().is_terminal();
+        if interactive {
+            answers.ask()?;
+        }
+        let store_token = answers.api_token.is_some() && (interactive || init.store_token);
+
+        tokio::fs::create_dir_all(&answers.cache_dir)
+            .await
+            .with_context(|| format!("failed to create cache directory {}", answers.cache_dir))?;
+        if let Some(parent) = path.parent().filter(|p| !p.as_os_str().is_empty()) {
+            tokio::fs::create_dir_all(parent).await?;
+        }
+        let mut file = create_configuration_file(&path, store_token).await?;
+        file.write_all(answers.to_toml(store_token)?.as_bytes())
+            .await?;
+        file.flush().await?;
+        let shadowed_by = shadowing_configuration_file(options, &path);
+
+        let checks = vec![
+            check_anki_connect(&answers.anki_endpoint).await,
+            check_api_token(answers.api_token.as_ref()).await,
+        ];
+        Ok(Self {
+            configuration_file: path.display().to_string(),
+            cache_dir: answers.cache_dir,
+            token_stored: store_token,
+            shadowed_by: shadowed_by.map(|p| p.display().to_string()),
+            checks,
+        })
+    }
+}
+
+impl InitAnswers {
+    fn ask(&mut self) -> anyhow::Result<()> {
+        self.kanji_deck = prompt("Kanji deck name", &self.kanji_deck)?;
+        self.vocabulary_deck = prompt("Vocabulary deck name", &self.vocabulary_deck)?;
+        self.cache_dir = prompt("Directory for downloaded data", &self.cache_dir)?;
+        self.anki_endpoint = prompt("Anki Connect address", &self.anki_endpoint)?;
+        if self.api_token.is_none() {
+            let token = rpassword::prompt_password(
+                "WaniKani API token, stored in the configuration file (leave empty to skip): ",
+            )?;
+            self.api_token = Some(ApiToken::new(&token)).filter(|t| !t.expose().is_empty());
+        }
+        Ok(())
+    }
+
+    fn to_toml(&self, store_token: bool) -> anyhow::Result<String> {
+        // JSON strings are valid TOML basic strings, which takes care of escaping.
+        let quote = serde_json::to_string::<str>;
+        let token = match (&self.api_token, store_token) {
+            (Some(token), true) => format!("api_token = {}", quote(token.expose())?),
+            _ => "# api_token = \"\"".to_owned(),
+        };
+        Ok(format!(
+            r#"# Configuration of wanikanji. See the README for every available option.
+
+# Directory that downloaded WaniKani data is stored in.
+cache_dir = {cache_dir}
+
+# Address of the Anki Connect add-on.
+anki_endpoint = {anki_endpoint}
+
+# WaniKani API token from https://www.wanikani.com/settings/personal_access_tokens. Instead of storing it here, it can
+# also be read from a file with token_file, or passed with $WANIKANJI_API_TOKEN or --api-token-stdin.
+{token}
+
+[kanji]
+deck_name = {kanji_deck}
+model_name = {kanji_deck}
+
+[vocabulary]
+deck_name = {vocabulary_deck}
+model_name = {vocabulary_deck}
+"#,
+            cache_dir = quote(&self.cache_dir)?,
+            anki_endpoint = quote(&self.anki_endpoint)?,
+            token = token,
+            kanji_deck = quote(&self.kanji_deck)?,
+            vocabulary_deck = quote(&self.vocabulary_deck)?,
+        ))
+    }
+}
+
+/// Ask a question on stderr, falling back to the default answer when the reply is empty.
+fn prompt(question: &str, default: &str) -> std::io::Result<String> {
+    eprint!("{} [{}]: ", question, default);
+    std::io::stderr().flush()?;
+    let mut answer = String::new();
+    std::io::stdin().lock().read_line(&mut answer)?;
+    let answer = answer.trim();
+    Ok(if answer.is_empty() { default } else { answer }.to_owned())
+}
+
+/// The cache directory of the user, which is used instead of the working directory so that wanikanji can run anywhere.
+fn default_cache_dir() -> String {
+    std::env::var_os("XDG_CACHE_HOME")
+        .map(PathBuf::from)
+        .or_else(|| std::env::var_os("HOME").map(|home| Path::new(&home).join(".cache")))
+        .map_or_else(
+            || ".cache".to_owned(),
+            |dir| dir.join("wanikanji").display().to_string(),
+        )
+}
+
+/// Create or truncate the configuration file. When it is going to contain the API token, it is made readable by its
+/// owner only before anything is written to it.
+async fn create_configuration_file(path: &Path, private: bool) -> std::io::Result<tokio::fs::File> {
+    let mut options = tokio::fs::OpenOptions::new();
+    options.write(true).create(true).truncate(true);
+    #[cfg(unix)]
+    if private {
+        options.mode(0o600);
+    }
+    let file = options.open(path).await?;
+    #[cfg(unix)]
+    if private {
+        // The mode only applies to new files, a file replaced with --force keeps its permissions.
+        use std::os::unix::fs::PermissionsExt;
+        file.set_permissions(std::fs::Permissions::from_mode(0o600))
+            .await?;
+    }
+    #[cfg(not(unix))]
+    let _ = private;
+    Ok(file)
+}
+
+impl fmt::Display for InitReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "wrote configuration to {}", self.configuration_file)?;
+        writeln!(f, "created cache directory {}", self.cache_dir)?;
+        if self.token_stored {
+            writeln!(f, "stored the API token in the configuration file")?;
+        }
+        if let Some(shadowing) = &self.shadowed_by {
+            writeln!(
+                f,
+                "warning: {} takes precedence over the new configuration file, remove it or pass --config",
+                shadowing
+            )?;
+        }
+        for check in &self.checks {
+            write!(f, "{}", check)?;
+        }
+        Ok(())
+    }
+}
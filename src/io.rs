@@ -1,4 +1,77 @@
+use crate::query::ApiSubjectMessage;
+use fs4::fs_std::FileExt;
+use rusqlite::OptionalExtension;
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, HashSet};
+use std::io::{Read, Write};
+use std::path::Path;
+use std::time::{Duration, SystemTime};
 use thiserror::Error;
+use tokio::io::AsyncWriteExt;
+
+/// The version of the layout of cached data. Bump it whenever a cached type changes in a way that older data no longer
+/// deserializes, and add a migration from the previous version to `MIGRATIONS` if the old data can be converted.
+pub const CACHE_SCHEMA_VERSION: u32 = 1;
+
+/// Migrations of cached data, where the migration at index `n` converts data of version `n` to version `n + 1`. A
+/// migration returns `None` when the data cannot be converted, in which case it has to be downloaded again.
+const MIGRATIONS: [Migration; CACHE_SCHEMA_VERSION as usize] = [migrate_bare];
+
+type Migration = fn(&str, serde_json::Value) -> Option<serde_json::Value>;
+
+/// Version 0 is the bare data that was cached before the envelope existed, and has the same layout as version 1.
+fn migrate_bare(_key: &str, data: serde_json::Value) -> Option<serde_json::Value> {
+    Some(data)
+}
+
+/// Bring data of the given version up to `CACHE_SCHEMA_VERSION`.
+fn migrate(
+    key: &str,
+    mut version: u32,
+    mut data: serde_json::Value,
+) -> Result<serde_json::Value, IoError> {
+    let outdated = |version| IoError::CacheOutdated {
+        key: key.to_owned(),
+        version,
+    };
+    while version < CACHE_SCHEMA_VERSION {
+        let migrate = MIGRATIONS[version as usize];
+        data = migrate(key, data).ok_or_else(|| outdated(version))?;
+        version += 1;
+    }
+    if version != CACHE_SCHEMA_VERSION {
+        return Err(outdated(version));
+    }
+    Ok(data)
+}
+
+/// Deserialize migrated data, which only fails when a cached type changed without bumping `CACHE_SCHEMA_VERSION`.
+fn deserialize<T: DeserializeOwned>(key: &str, data: serde_json::Value) -> Result<T, IoError> {
+    serde_json::from_value(data).map_err(|_| IoError::CacheOutdated {
+        key: key.to_owned(),
+        version: CACHE_SCHEMA_VERSION,
+    })
+}
+
+fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(SystemTime::UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs()
+}
+
+/// The wrapper that every cached item is stored in, which records where the data came from and in which layout it
+/// was written.
+#[derive(Debug, Deserialize, Serialize)]
+pub struct CacheEnvelope<T> {
+    pub schema_version: u32,
+    /// When the item was written, in seconds since the Unix epoch.
+    pub created_at: u64,
+    /// Where the data came from, such as the WaniKani endpoint it was downloaded from.
+    pub source: String,
+    pub data: T,
+}
 
 #[derive(Debug, Error)]
 pub enum IoError {
@@ -10,41 +83,860 @@ pub enum IoError {
     CacheDirectoryNotFound,
     #[error("cache item not found")]
     CacheItemNotFound,
+    #[error("cached {key} was written in an outdated format (version {version}), it has to be downloaded again")]
+    CacheOutdated { key: String, version: u32 },
+    #[error("cache database error: {0}")]
+    DatabaseError(#[from] rusqlite::Error),
+}
+
+impl IoError {
+    /// A stable identifier of the kind of error, for scripts that consume the JSON output.
+    pub fn code(&self) -> &'static str {
+        match self {
+            IoError::Io(_) => "io-error",
+            IoError::SerdeError(_) => "cache-invalid",
+            IoError::CacheDirectoryNotFound => "cache-directory-not-found",
+            IoError::CacheItemNotFound => "cache-item-not-found",
+            IoError::CacheOutdated { .. } => "cache-outdated",
+            IoError::DatabaseError(_) => "cache-database-error",
+        }
+    }
+}
+
+/// The file in the cache directory that commands lock while they use the cache.
+const LOCK_FILE: &str = ".lock";
+
+/// Which storage the cache uses.
+#[derive(Debug, Clone, Copy, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum CacheBackend {
+    /// A JSON file per cached item.
+    Json,
+    /// A SQLite database, in which subjects can be looked up without loading all of them.
+    Sqlite,
+}
+
+/// Which subjects to read from the cache. Every criterion that is not empty has to match.
+#[derive(Debug, Default)]
+pub struct SubjectFilter {
+    pub ids: Vec<i64>,
+    pub levels: Vec<i64>,
+    pub characters: Vec<String>,
+}
+
+impl SubjectFilter {
+    /// Whether a subject, in the form it is cached in, matches the filter.
+    pub fn matches(&self, subject: &serde_json::Value) -> bool {
+        let id = subject.get("id").and_then(serde_json::Value::as_i64);
+        let level = subject.get("level").and_then(serde_json::Value::as_i64);
+        let characters = subject
+            .get("characters")
+            .and_then(serde_json::Value::as_str);
+        self.matches_properties(id, level, characters)
+    }
+
+    /// Whether a subject that was already read from the cache matches the filter.
+    pub fn matches_subject(&self, subject: &ApiSubjectMessage) -> bool {
+        self.matches_properties(
+            Some(subject.id),
+            Some(subject.level.into()),
+            subject.characters.as_deref(),
+        )
+    }
+
+    fn matches_properties(
+        &self,
+        id: Option<i64>,
+        level: Option<i64>,
+        characters: Option<&str>,
+    ) -> bool {
+        (self.ids.is_empty() || id.is_some_and(|id| self.ids.contains(&id)))
+            && (self.levels.is_empty() || level.is_some_and(|level| self.levels.contains(&level)))
+            && (self.characters.is_empty()
+                || characters.is_some_and(|c| self.characters.iter().any(|f| f == c)))
+    }
+}
+
+/// Storage for downloaded data and install checkpoints, which are stored under a key each.
+// Commands use the cache from a single task, so its futures do not have to be `Send`.
+#[allow(async_fn_in_trait)]
+pub trait Cache {
+    /// Where the cache is stored, for reporting.
+    fn location(&self) -> &str;
+
+    /// Write a serializable value to the cache, along with where it came from.
+    async fn insert<T>(&self, key: &str, source: &str, value: T) -> Result<(), IoError>
+    where
+        T: serde::Serialize;
+
+    /// Read an item from the cache, and deserialize it into the expected type.
+    ///
+    /// Items written in an older layout are migrated. Items that cannot be migrated, or that no longer deserialize
+    /// into the expected type, yield `IoError::CacheOutdated`.
+    async fn get<T>(&self, key: &str) -> Result<Option<T>, IoError>
+    where
+        T: for<'de> serde::Deserialize<'de>;
+
+    /// Read the subjects stored under a key that match the filter.
+    async fn subjects<T>(
+        &self,
+        key: &str,
+        filter: &SubjectFilter,
+    ) -> Result<Option<Vec<T>>, IoError>
+    where
+        T: for<'de> serde::Deserialize<'de>,
+    {
+        let Some(subjects) = self.get::<Vec<serde_json::Value>>(key).await? else {
+            return Ok(None);
+        };
+        subjects
+            .into_iter()
+            .filter(|subject| filter.matches(subject))
+            .map(|subject| deserialize(key, subject))
+            .collect::<Result<_, _>>()
+            .map(Some)
+    }
+
+    /// Get the time an item was last written to the cache.
+    async fn modified(&self, key: &str) -> Result<SystemTime, IoError>;
+
+    /// Remove an item from the cache, if it exists.
+    async fn remove(&self, key: &str) -> Result<(), IoError>;
+}
+
+/// Lock the cache directory, waiting for other commands that use it to finish.
+async fn lock_cache_dir(cache_dir: &str) -> Result<std::fs::File, IoError> {
+    let lock = std::fs::OpenOptions::new()
+        .create(true)
+        .truncate(false)
+        .write(true)
+        .open(Path::new(cache_dir).join(LOCK_FILE))?;
+    if lock.try_lock_exclusive()? {
+        return Ok(lock);
+    }
+    tracing::info!(
+        "waiting for another wanikanji command to finish using {}",
+        cache_dir
+    );
+    blocking(move || lock.lock_exclusive().map(|()| lock)).await
+}
+
+/// Run blocking file system work without blocking the runtime. A panic of the work is reported as an io error.
+async fn blocking<T: Send + 'static>(
+    work: impl FnOnce() -> std::io::Result<T> + Send + 'static,
+) -> Result<T, IoError> {
+    Ok(tokio::task::spawn_blocking(work)
+        .await
+        .map_err(std::io::Error::other)??)
+}
+
+/// How the files of the JSON cache are compressed.
+#[derive(Debug, Clone, Copy, PartialEq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum CacheCompression {
+    None,
+    Gzip,
+    Zstd,
+}
+
+impl CacheCompression {
+    const ALL: [CacheCompression; 3] = [
+        CacheCompression::None,
+        CacheCompression::Gzip,
+        CacheCompression::Zstd,
+    ];
+
+    fn extension(self) -> &'static str {
+        match self {
+            CacheCompression::None => "json",
+            CacheCompression::Gzip => "json.gz",
+            CacheCompression::Zstd => "json.zst",
+        }
+    }
+
+    fn compress(self, json: Vec<u8>) -> Result<Vec<u8>, IoError> {
+        match self {
+            CacheCompression::None => Ok(json),
+            CacheCompression::Gzip => {
+                let mut encoder =
+                    flate2::write::GzEncoder::new(Vec::new(), flate2::Compression::default());
+                encoder.write_all(&json)?;
+                Ok(encoder.finish()?)
+            }
+            CacheCompression::Zstd => Ok(zstd::encode_all(json.as_slice(), 0)?),
+        }
+    }
+
+    /// Decompress a file by the format its header announces, so that it does not matter which extension it has.
+    fn decompress(contents: Vec<u8>) -> Result<Vec<u8>, IoError> {
+        const GZIP_MAGIC: [u8; 2] = [0x1f, 0x8b];
+        const ZSTD_MAGIC: [u8; 4] = [0x28, 0xb5, 0x2f, 0xfd];
+        if contents.starts_with(&GZIP_MAGIC) {
+            let mut json = Vec::new();
+            flate2::read::GzDecoder::new(contents.as_slice()).read_to_end(&mut json)?;
+            Ok(json)
+        } else if contents.starts_with(&ZSTD_MAGIC) {
+            Ok(zstd::decode_all(contents.as_slice())?)
+        } else {
+            Ok(contents)
+        }
+    }
 }
 
 /// A container that can read and write cached data to the file system
+///
+/// The cache directory is locked for as long as the cache exists, so that concurrent commands do not overwrite each
+/// other's data. The lock is advisory, and is released when the cache is dropped or the process exits.
+///
+/// Items are written with the configured compression, and read whatever compression they were written with.
 pub struct FilesystemCache<'a> {
     pub cache_dir: &'a str,
+    compression: CacheCompression,
+    _lock: std::fs::File,
 }
 
 impl<'a> FilesystemCache<'a> {
-    pub async fn new(cache_dir: &'a str) -> Result<Self, IoError> {
+    pub async fn new(cache_dir: &'a str, compression: CacheCompression) -> Result<Self, IoError> {
         if tokio::fs::metadata(cache_dir).await.is_err() {
             return Err(IoError::CacheDirectoryNotFound);
         }
-        Ok(Self { cache_dir })
+        Ok(Self {
+            cache_dir,
+            compression,
+            _lock: lock_cache_dir(cache_dir).await?,
+        })
     }
 
-    /// Write a serializable value to the cache.
-    pub async fn insert<T>(&self, key: &str, value: T) -> Result<(), IoError>
+    fn path(&self, key: &str, compression: CacheCompression) -> String {
+        format!("{}/{}.{}", self.cache_dir, key, compression.extension())
+    }
+
+    /// Find the file an item is stored in, whichever compression it was written with.
+    async fn find(&self, key: &str) -> Result<(String, std::fs::Metadata), IoError> {
+        for compression in CacheCompression::ALL {
+            let path = self.path(key, compression);
+            match tokio::fs::metadata(&path).await {
+                Ok(metadata) => return Ok((path, metadata)),
+                Err(e) if e.kind() == std::io::ErrorKind::NotFound => continue,
+                Err(e) => return Err(e.into()),
+            }
+        }
+        Err(IoError::CacheItemNotFound)
+    }
+}
+
+impl Cache for FilesystemCache<'_> {
+    fn location(&self) -> &str {
+        self.cache_dir
+    }
+
+    async fn insert<T>(&self, key: &str, source: &str, value: T) -> Result<(), IoError>
     where
         T: serde::Serialize,
     {
-        let path = format!("{}/{}.json", self.cache_dir, key);
-        tokio::fs::write(path, serde_json::to_string_pretty(&value)?).await?;
+        let path = self.path(key, self.compression);
+        let envelope = CacheEnvelope {
+            schema_version: CACHE_SCHEMA_VERSION,
+            created_at: now(),
+            source: source.to_owned(),
+            data: value,
+        };
+        // Compressed files are not meant to be read by people, so they skip the indentation.
+        let contents = match self.compression {
+            CacheCompression::None => serde_json::to_vec_pretty(&envelope)?,
+            compression => compression.compress(serde_json::to_vec(&envelope)?)?,
+        };
+        // A crash while writing only leaves the temporary file behind, because renaming replaces the item atomically.
+        let temporary = format!("{}.tmp", path);
+        let mut file = tokio::fs::File::create(&temporary).await?;
+        file.write_all(&contents).await?;
+        file.sync_all().await?;
+        tokio::fs::rename(&temporary, &path).await?;
+        sync_dir(self.cache_dir).await?;
+        // A copy written with another compression would otherwise be found instead, once the compression changes again.
+        for compression in CacheCompression::ALL {
+            if compression != self.compression {
+                remove_if_exists(&self.path(key, compression)).await?;
+            }
+        }
         Ok(())
     }
 
-    /// Read an item from the cache, and deserialize it into the expected type.
-    pub async fn get<T>(&self, key: &str) -> Result<Option<T>, IoError>
+    async fn get<T>(&self, key: &str) -> Result<Option<T>, IoError>
+    where
+        T: for<'de> serde::Deserialize<'de>,
+    {
+        let (path, _) = self.find(key).await?;
+        let contents = CacheCompression::decompress(tokio::fs::read(path).await?)?;
+        let value = serde_json::from_slice::<serde_json::Value>(&contents)?;
+        let (version, data) = match value {
+            serde_json::Value::Object(mut object) if object.contains_key("schema_version") => {
+                let version = object
+                    .get("schema_version")
+                    .and_then(serde_json::Value::as_u64)
+                    .and_then(|v| u32::try_from(v).ok())
+                    .unwrap_or(u32::MAX);
+                (version, object.remove("data").unwrap_or_default())
+            }
+            bare => (0, bare),
+        };
+        deserialize(key, migrate(key, version, data)?).map(Some)
+    }
+
+    async fn modified(&self, key: &str) -> Result<SystemTime, IoError> {
+        let (_, metadata) = self.find(key).await?;
+        Ok(metadata.modified()?)
+    }
+
+    async fn remove(&self, key: &str) -> Result<(), IoError> {
+        for compression in CacheCompression::ALL {
+            remove_if_exists(&self.path(key, compression)).await?;
+        }
+        Ok(())
+    }
+}
+
+/// Sync a directory, so that a rename inside it survives a crash. Only unix can open a directory to sync it.
+async fn sync_dir(dir: &str) -> Result<(), IoError> {
+    #[cfg(unix)]
+    tokio::fs::File::open(dir).await?.sync_all().await?;
+    #[cfg(not(unix))]
+    let _ = dir;
+    Ok(())
+}
+
+async fn remove_if_exists(path: &str) -> Result<(), IoError> {
+    match tokio::fs::remove_file(path).await {
+        Ok(()) => Ok(()),
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(()),
+        Err(e) => Err(e.into()),
+    }
+}
+
+/// The name of the database in the cache directory.
+const DATABASE_FILE: &str = "cache.sqlite3";
+
+/// The keys whose items are lists of WaniKani subjects, which are stored a row per subject.
+const SUBJECT_KEYS: [&str; 2] = ["kanji", "vocabulary"];
+
+const SCHEMA: &str = "
+CREATE TABLE IF NOT EXISTS sync_metadata (
+    key TEXT PRIMARY KEY,
+    schema_version INTEGER NOT NULL,
+    created_at INTEGER NOT NULL,
+    source TEXT NOT NULL
+);
+CREATE TABLE IF NOT EXISTS subjects (
+    id INTEGER PRIMARY KEY,
+    object TEXT NOT NULL,
+    level INTEGER NOT NULL,
+    characters TEXT,
+    data_updated_at TEXT,
+    data TEXT NOT NULL
+);
+CREATE INDEX IF NOT EXISTS subjects_object_level ON subjects (object, level);
+CREATE INDEX IF NOT EXISTS subjects_characters ON subjects (characters);
+CREATE TABLE IF NOT EXISTS media (
+    url TEXT PRIMARY KEY,
+    subject_id INTEGER NOT NULL,
+    content_type TEXT NOT NULL
+);
+CREATE INDEX IF NOT EXISTS media_subject_id ON media (subject_id);
+CREATE TABLE IF NOT EXISTS items (
+    key TEXT PRIMARY KEY,
+    data TEXT NOT NULL
+);
+";
+
+/// A cache that stores its items in a SQLite database inside the cache directory.
+///
+/// Subjects are stored a row per subject, indexed by identifier, level and characters, so that a few of them can be
+/// read without loading all of them, and downloading them again only writes the subjects that changed. Pronunciation
+/// audio is indexed in the media table. Every other item, such as install checkpoints, is stored as a single JSON document.
+///
+/// The database is queried synchronously, which is fine because commands use the cache from a single task.
+pub struct SqliteCache {
+    path: String,
+    connection: rusqlite::Connection,
+    _lock: std::fs::File,
+}
+
+impl SqliteCache {
+    pub async fn new(cache_dir: &str) -> Result<Self, IoError> {
+        if tokio::fs::metadata(cache_dir).await.is_err() {
+            return Err(IoError::CacheDirectoryNotFound);
+        }
+        let lock = lock_cache_dir(cache_dir).await?;
+        let path = Path::new(cache_dir).join(DATABASE_FILE);
+        let connection = rusqlite::Connection::open(&path)?;
+        connection.execute_batch(SCHEMA)?;
+        Ok(Self {
+            path: path.display().to_string(),
+            connection,
+            _lock: lock,
+        })
+    }
+
+    /// The schema version an item was written with, or `None` if the item does not exist.
+    fn schema_version(&self, key: &str) -> Result<Option<u32>, IoError> {
+        Ok(self
+            .connection
+            .query_row(
+                "SELECT schema_version FROM sync_metadata WHERE key = ?1",
+                [key],
+                |row| row.get(0),
+            )
+            .optional()?)
+    }
+
+    /// Read the subjects stored under a key that match the filter, as a list in the cached layout.
+    fn read_subjects(
+        &self,
+        key: &str,
+        filter: &SubjectFilter,
+    ) -> Result<serde_json::Value, IoError> {
+        let Some(version) = self.schema_version(key)? else {
+            return Err(IoError::CacheItemNotFound);
+        };
+        let mut sql = "SELECT data FROM subjects WHERE object = ?".to_owned();
+        let mut params = vec![rusqlite::types::Value::from(key.to_owned())];
+        let criteria: [(&str, Vec<rusqlite::types::Value>); 3] = [
+            ("id", filter.ids.iter().map(|&id| id.into()).collect()),
+            (
+                "level",
+                filter.levels.iter().map(|&level| level.into()).collect(),
+            ),
+            (
+                "characters",
+                filter.characters.iter().map(|c| c.clone().into()).collect(),
+            ),
+        ];
+        for (column, values) in criteria {
+            if values.is_empty() {
+                continue;
+            }
+            let placeholders = vec!["?"; values.len()].join(", ");
+            sql.push_str(&format!(" AND {} IN ({})", column, placeholders));
+            params.extend(values);
+        }
+        sql.push_str(" ORDER BY id");
+        let mut statement = self.connection.prepare(&sql)?;
+        let subjects = statement
+            .query_map(rusqlite::params_from_iter(params), |row| {
+                row.get::<_, String>(0)
+            })?
+            .map(|data| Ok(serde_json::from_str(&data?)?))
+            .collect::<Result<Vec<serde_json::Value>, IoError>>()?;
+        migrate(key, version, serde_json::Value::Array(subjects))
+    }
+
+    /// Replace the subjects stored under a key, only writing the subjects that changed since they were last stored.
+    fn write_subjects(
+        &self,
+        transaction: &rusqlite::Transaction,
+        key: &str,
+        subjects: &[serde_json::Value],
+    ) -> Result<(), IoError> {
+        let stored = transaction
+            .prepare("SELECT id, data_updated_at FROM subjects WHERE object = ?1")?
+            .query_map([key], |row| {
+                Ok((row.get::<_, i64>(0)?, row.get::<_, Option<String>>(1)?))
+            })?
+            .collect::<Result<HashMap<_, _>, _>>()?;
+        // Subjects stored in another layout are rewritten even when they did not change on WaniKani, otherwise reading
+        // them keeps failing until they do.
+        let rewrite = self.schema_version(key)? != Some(CACHE_SCHEMA_VERSION);
+        let mut upsert = transaction.prepare(
+            "INSERT INTO subjects (id, object, level, characters, data_updated_at, data)
+             VALUES (?1, ?2, ?3, ?4, ?5, ?6)
+             ON CONFLICT (id) DO UPDATE SET
+                 object = excluded.object,
+                 level = excluded.level,
+                 characters = excluded.characters,
+                 data_updated_at = excluded.data_updated_at,
+                 data = excluded.data",
+        )?;
+        let mut delete_media = transaction.prepare("DELETE FROM media WHERE subject_id = ?1")?;
+        let mut insert_media = transaction.prepare(
+            "INSERT OR REPLACE INTO media (url, subject_id, content_type) VALUES (?1, ?2, ?3)",
+        )?;
+        let mut seen = HashSet::new();
+        for subject in subjects {
+            let id = subject
+                .get("id")
+                .and_then(serde_json::Value::as_i64)
+                .unwrap_or_default();
+            let updated_at = subject
+                .get("data_updated_at")
+                .and_then(serde_json::Value::as_str)
+                .map(str::to_owned);
+            seen.insert(id);
+            // Subjects without a modification time cannot be compared, so they are always written.
+            if !rewrite && updated_at.is_some() && stored.get(&id) == Some(&updated_at) {
+                continue;
+            }
+            upsert.execute(rusqlite::params![
+                id,
+                key,
+                subject
+                    .get("level")
+                    .and_then(serde_json::Value::as_i64)
+                    .unwrap_or_default(),
+                subject
+                    .get("characters")
+                    .and_then(serde_json::Value::as_str),
+                updated_at,
+                serde_json::to_string(subject)?,
+            ])?;
+            delete_media.execute([id])?;
+            let audios = subject
+                .get("pronunciation_audios")
+                .and_then(serde_json::Value::as_array);
+            for audio in audios.into_iter().flatten() {
+                let url = audio.get("url").and_then(serde_json::Value::as_str);
+                let content_type = audio
+                    .get("content_type")
+                    .and_then(serde_json::Value::as_str);
+                if let (Some(url), Some(content_type)) = (url, content_type) {
+                    insert_media.execute(rusqlite::params![url, id, content_type])?;
+                }
+            }
+        }
+        for id in stored.keys().filter(|id| !seen.contains(id)) {
+            transaction.execute("DELETE FROM subjects WHERE id = ?1", [id])?;
+            delete_media.execute([id])?;
+        }
+        Ok(())
+    }
+}
+
+impl Cache for SqliteCache {
+    fn location(&self) -> &str {
+        &self.path
+    }
+
+    async fn insert<T>(&self, key: &str, source: &str, value: T) -> Result<(), IoError>
+    where
+        T: serde::Serialize,
+    {
+        let value = serde_json::to_value(value)?;
+        let transaction = self.connection.unchecked_transaction()?;
+        match (SUBJECT_KEYS.contains(&key), &value) {
+            (true, serde_json::Value::Array(subjects)) => {
+                self.write_subjects(&transaction, key, subjects)?
+            }
+            _ => {
+                transaction.execute(
+                    "INSERT OR REPLACE INTO items (key, data) VALUES (?1, ?2)",
+                    rusqlite::params![key, serde_json::to_string(&value)?],
+                )?;
+            }
+        }
+        transaction.execute(
+            "INSERT OR REPLACE INTO sync_metadata (key, schema_version, created_at, source)
+             VALUES (?1, ?2, ?3, ?4)",
+            // SQLite integers are signed, which leaves plenty of room for the seconds since the Unix epoch.
+            rusqlite::params![key, CACHE_SCHEMA_VERSION, now() as i64, source],
+        )?;
+        transaction.commit()?;
+        Ok(())
+    }
+
+    async fn get<T>(&self, key: &str) -> Result<Option<T>, IoError>
     where
         T: for<'de> serde::Deserialize<'de>,
     {
-        let path = format!("{}/{}.json", self.cache_dir, key);
-        if tokio::fs::metadata(&path).await.is_err() {
+        if SUBJECT_KEYS.contains(&key) {
+            let subjects = self.read_subjects(key, &SubjectFilter::default())?;
+            return deserialize(key, subjects).map(Some);
+        }
+        let Some(version) = self.schema_version(key)? else {
             return Err(IoError::CacheItemNotFound);
+        };
+        let data = self
+            .connection
+            .query_row("SELECT data FROM items WHERE key = ?1", [key], |row| {
+                row.get::<_, String>(0)
+            })
+            .optional()?
+            .ok_or(IoError::CacheItemNotFound)?;
+        deserialize(key, migrate(key, version, serde_json::from_str(&data)?)?).map(Some)
+    }
+
+    async fn subjects<T>(
+        &self,
+        key: &str,
+        filter: &SubjectFilter,
+    ) -> Result<Option<Vec<T>>, IoError>
+    where
+        T: for<'de> serde::Deserialize<'de>,
+    {
+        deserialize(key, self.read_subjects(key, filter)?).map(Some)
+    }
+
+    async fn modified(&self, key: &str) -> Result<SystemTime, IoError> {
+        let created_at = self
+            .connection
+            .query_row(
+                "SELECT created_at FROM sync_metadata WHERE key = ?1",
+                [key],
+                |row| row.get::<_, i64>(0),
+            )
+            .optional()?
+            .ok_or(IoError::CacheItemNotFound)?;
+        Ok(SystemTime::UNIX_EPOCH + Duration::from_secs(created_at.max(0) as u64))
+    }
+
+    async fn remove(&self, key: &str) -> Result<(), IoError> {
+        let transaction = self.connection.unchecked_transaction()?;
+        transaction.execute(
+            "DELETE FROM media WHERE subject_id IN (SELECT id FROM subjects WHERE object = ?1)",
+            [key],
+        )?;
+        transaction.execute("DELETE FROM subjects WHERE object = ?1", [key])?;
+        transaction.execute("DELETE FROM items WHERE key = ?1", [key])?;
+        transaction.execute("DELETE FROM sync_metadata WHERE key = ?1", [key])?;
+        transaction.commit()?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::{json, Value};
+    use std::path::PathBuf;
+    use std::sync::atomic::{AtomicBool, Ordering};
+
+    /// An empty cache directory that is unique to the test.
+    fn cache_dir(test: &str) -> PathBuf {
+        let dir =
+            std::env::temp_dir().join(format!("wanikanji-io-{}-{}", test, std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    fn file_names(dir: &Path) -> Vec<String> {
+        let mut names = std::fs::read_dir(dir)
+            .unwrap()
+            .map(|entry| entry.unwrap().file_name().to_string_lossy().into_owned())
+            .collect::<Vec<_>>();
+        names.sort();
+        names
+    }
+
+    #[test]
+    fn bare_data_migrates_to_the_current_version() {
+        let data = json!([{ "id": 1, "object": "kanji" }]);
+        assert_eq!(migrate("kanji", 0, data.clone()).unwrap(), data);
+    }
+
+    #[test]
+    fn current_data_is_not_migrated() {
+        let data = json!({ "id": 1 });
+        assert_eq!(
+            migrate("kanji", CACHE_SCHEMA_VERSION, data.clone()).unwrap(),
+            data
+        );
+    }
+
+    #[test]
+    fn data_of_a_future_version_is_outdated() {
+        let version = CACHE_SCHEMA_VERSION + 1;
+        match migrate("kanji", version, json!([])) {
+            Err(IoError::CacheOutdated { key, version: v }) => {
+                assert_eq!(key, "kanji");
+                assert_eq!(v, version);
+            }
+            other => panic!("expected outdated data, got {:?}", other),
         }
-        let value = tokio::fs::read_to_string(path).await?;
-        Ok(Some(serde_json::from_str(&value)?))
+    }
+
+    #[tokio::test]
+    async fn subjects_of_another_version_are_rewritten() {
+        let dir = cache_dir("sqlite-rewrite");
+        let cache = SqliteCache::new(&dir.display().to_string()).await.unwrap();
+        let subjects = json!([{ "id": 1, "level": 1, "data_updated_at": "2024-01-01T00:00:00Z" }]);
+        cache.insert("kanji", "test", &subjects).await.unwrap();
+        // Pretend an older version stored the subject in a layout that reads differently.
+        cache
+            .connection
+            .execute_batch(
+                "UPDATE sync_metadata SET schema_version = 0;
+                 UPDATE subjects SET data = '{\"old\": true}';",
+            )
+            .unwrap();
+
+        cache.insert("kanji", "test", &subjects).await.unwrap();
+
+        let stored = cache.get::<serde_json::Value>("kanji").await.unwrap();
+        assert_eq!(stored, Some(subjects));
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn insert_leaves_no_temporary_files_behind() {
+        let dir = cache_dir("insert-temporary");
+        let path = dir.display().to_string();
+        let cache = FilesystemCache::new(&path, CacheCompression::None)
+            .await
+            .unwrap();
+        cache.insert("kanji", "test", json!([1])).await.unwrap();
+        cache.insert("kanji", "test", json!([2])).await.unwrap();
+
+        assert_eq!(file_names(&dir), [".lock", "kanji.json"]);
+        assert_eq!(cache.get::<Value>("kanji").await.unwrap(), Some(json!([2])));
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn failed_insert_keeps_the_previous_item() {
+        let dir = cache_dir("insert-failed");
+        let path = dir.display().to_string();
+        let cache = FilesystemCache::new(&path, CacheCompression::None)
+            .await
+            .unwrap();
+        cache.insert("kanji", "test", json!([1])).await.unwrap();
+        // A directory in place of the temporary file makes the write fail before anything is renamed.
+        std::fs::create_dir(dir.join("kanji.json.tmp")).unwrap();
+
+        assert!(cache.insert("kanji", "test", json!([2])).await.is_err());
+
+        assert_eq!(cache.get::<Value>("kanji").await.unwrap(), Some(json!([1])));
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn second_cache_waits_for_the_lock() {
+        let dir = cache_dir("lock");
+        let path = dir.display().to_string();
+        let first = FilesystemCache::new(&path, CacheCompression::None)
+            .await
+            .unwrap();
+        let released = AtomicBool::new(false);
+
+        let second = async {
+            let cache = FilesystemCache::new(&path, CacheCompression::None)
+                .await
+                .unwrap();
+            assert!(released.load(Ordering::SeqCst), "locked before release");
+            cache
+        };
+        let release = async {
+            tokio::time::sleep(Duration::from_millis(200)).await;
+            released.store(true, Ordering::SeqCst);
+            drop(first);
+        };
+        tokio::join!(second, release);
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn panicking_blocking_work_is_an_io_error() {
+        let result = blocking(|| -> std::io::Result<()> { panic!("lock failed") }).await;
+        match result {
+            Err(e @ IoError::Io(_)) => assert_eq!(e.code(), "io-error"),
+            other => panic!("expected an io error, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn compressed_data_decompresses_by_its_header() {
+        let json = br#"{"schema_version":1,"data":[]}"#.to_vec();
+        for compression in CacheCompression::ALL {
+            let compressed = compression.compress(json.clone()).unwrap();
+            assert_eq!(
+                CacheCompression::decompress(compressed).unwrap(),
+                json,
+                "{:?}",
+                compression
+            );
+        }
+    }
+
+    #[test]
+    fn uncompressed_data_is_read_as_is() {
+        let json = br#"[{"id": 1}]"#.to_vec();
+        assert_eq!(CacheCompression::decompress(json.clone()).unwrap(), json);
+    }
+
+    #[tokio::test]
+    async fn compressed_items_round_trip() {
+        for (compression, name, magic) in [
+            (CacheCompression::Gzip, "kanji.json.gz", &[0x1f, 0x8b][..]),
+            (
+                CacheCompression::Zstd,
+                "kanji.json.zst",
+                &[0x28, 0xb5, 0x2f, 0xfd][..],
+            ),
+        ] {
+            let dir = cache_dir(&format!("round-trip-{:?}", compression));
+            let path = dir.display().to_string();
+            let cache = FilesystemCache::new(&path, compression).await.unwrap();
+            cache
+                .insert("kanji", "test", json!([{ "id": 1 }]))
+                .await
+                .unwrap();
+
+            assert!(std::fs::read(dir.join(name)).unwrap().starts_with(magic));
+            assert_eq!(
+                cache.get::<Value>("kanji").await.unwrap(),
+                Some(json!([{ "id": 1 }]))
+            );
+            drop(cache);
+            std::fs::remove_dir_all(dir).unwrap();
+        }
+    }
+
+    #[tokio::test]
+    async fn items_are_read_whatever_their_extension_says() {
+        let dir = cache_dir("wrong-extension");
+        let envelope = json!({ "schema_version": CACHE_SCHEMA_VERSION, "data": [1] });
+        let compressed = CacheCompression::Zstd
+            .compress(envelope.to_string().into_bytes())
+            .unwrap();
+        std::fs::write(dir.join("kanji.json"), compressed).unwrap();
+        let path = dir.display().to_string();
+        let cache = FilesystemCache::new(&path, CacheCompression::None)
+            .await
+            .unwrap();
+
+        assert_eq!(cache.get::<Value>("kanji").await.unwrap(), Some(json!([1])));
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn uncompressed_items_are_read_by_a_compressing_cache() {
+        let dir = cache_dir("uncompressed");
+        let envelope = json!({ "schema_version": CACHE_SCHEMA_VERSION, "data": [1] });
+        std::fs::write(dir.join("kanji.json"), envelope.to_string()).unwrap();
+        let path = dir.display().to_string();
+        let cache = FilesystemCache::new(&path, CacheCompression::Gzip)
+            .await
+            .unwrap();
+
+        assert_eq!(cache.get::<Value>("kanji").await.unwrap(), Some(json!([1])));
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn insert_removes_copies_in_other_compressions() {
+        let dir = cache_dir("other-compressions");
+        let path = dir.display().to_string();
+        let cache = FilesystemCache::new(&path, CacheCompression::None)
+            .await
+            .unwrap();
+        cache.insert("kanji", "test", json!([1])).await.unwrap();
+        drop(cache);
+
+        let cache = FilesystemCache::new(&path, CacheCompression::Zstd)
+            .await
+            .unwrap();
+        cache.insert("kanji", "test", json!([2])).await.unwrap();
+
+        assert_eq!(file_names(&dir), [".lock", "kanji.json.zst"]);
+        assert_eq!(cache.get::<Value>("kanji").await.unwrap(), Some(json!([2])));
+        std::fs::remove_dir_all(dir).unwrap();
     }
 }
@@ -1,12 +1,19 @@
+use crate::progress::Progress;
 use crate::query::{
     ApiCollectionMessage, ApiObjectMessage, ApiSubjectMessage, QueryClient, QueryError,
 };
 use serde::{Deserialize, Serialize};
 
+/// The WaniKani endpoint that lists every kanji subject.
+pub const KANJI_URL: &str = "https://api.wanikani.com/v2/subjects?types=kanji";
+
 impl QueryClient {
-    #[tracing::instrument(skip(self), err)]
-    pub async fn list_kanji(&self) -> Result<Vec<ApiKanjiMessage>, QueryError> {
-        let mut next_url = Some("https://api.wanikani.com/v2/subjects?types=kanji".to_owned());
+    #[tracing::instrument(skip(self, progress), err)]
+    pub async fn list_kanji(
+        &self,
+        progress: &Progress,
+    ) -> Result<Vec<ApiKanjiMessage>, QueryError> {
+        let mut next_url = Some(KANJI_URL.to_owned());
         let mut kanji = Vec::new();
 
         while let Some(url) = next_url {
@@ -14,15 +21,22 @@ impl QueryClient {
             let response = self
                 .get::<ApiCollectionMessage<ApiObjectMessage<ApiKanjiMessage>>>(&url)
                 .await?;
-            let items = response.data.into_iter().map(|o| o.data);
+            progress.set_length(response.page_count());
+            let items = response.data.into_iter().map(|o| {
+                let mut item = o.data;
+                item.subject.id = o.id.unwrap_or_default();
+                item.subject.data_updated_at = o.data_updated_at;
+                item
+            });
             kanji.extend(items);
+            progress.inc(1);
             next_url = response.pages.next_url;
         }
         Ok(kanji)
     }
 }
 
-#[derive(Debug, Deserialize, Serialize)]
+#[derive(Debug, Default, Deserialize, Serialize)]
 pub struct ApiKanjiMessage {
     #[serde(flatten)]
     pub subject: ApiSubjectMessage,
@@ -37,6 +51,19 @@ pub struct ApiKanjiMessage {
     pub visually_similar_subject_ids: Vec<i32>,
 }
 
+impl ApiKanjiMessage {
+    /// The properties of a kanji on top of those that every subject has.
+    pub const PROPERTIES: [&'static str; 7] = [
+        "amalgamation_subject_ids",
+        "component_subject_ids",
+        "meaning_hint",
+        "reading_hint",
+        "reading_mnemonic",
+        "readings",
+        "visually_similar_subject_ids",
+    ];
+}
+
 #[derive(Debug, Deserialize, Serialize)]
 pub struct ApiKanjiReadingMessage {
     pub reading: String,
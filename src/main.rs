@@ -1,16 +1,34 @@
 use crate::anki_connect::client::AnkiClient;
-use crate::io::FilesystemCache;
+use crate::configuration::{Configuration, ConfigurationLayers};
+use crate::io::{Cache, CacheBackend, FilesystemCache, SqliteCache};
+use crate::output::CommandOutput;
+use crate::progress::Summary;
 use crate::query::QueryClient;
 use clap::Parser;
-use config::Config;
-use serde::Deserialize;
+use std::path::PathBuf;
+use std::time::Instant;
+use tracing_subscriber::EnvFilter;
 
 pub mod anki;
 pub mod anki_connect;
 pub mod app;
+pub mod checkpoint;
+pub mod configuration;
+pub mod doctor;
+pub mod fields;
+pub mod furigana;
+pub mod init;
 pub mod io;
 pub mod kanji;
+pub mod output;
+pub mod plan;
+pub mod progress;
 pub mod query;
+pub mod resources;
+pub mod status;
+pub mod template;
+pub mod token;
+pub mod user;
 pub mod vocabulary;
 
 #[derive(clap::Parser)]
@@ -18,16 +36,44 @@ pub mod vocabulary;
 pub struct Options {
     #[clap(subcommand)]
     pub command: Command,
-    #[clap(long, default_value = ".cache")]
-    pub cache_dir: String,
-    #[clap(long)]
+    #[clap(long, help = "Configuration file to use instead of searching for one")]
+    pub config: Option<PathBuf>,
+    #[clap(long, help = "Profile of the configuration file to use")]
+    pub profile: Option<String>,
+    #[clap(long, help = "Directory to store downloaded data in [default: .cache]")]
+    pub cache_dir: Option<String>,
+    #[clap(
+        long,
+        help = "WaniKani API token, prefer $WANIKANJI_API_TOKEN as this is visible in process listings"
+    )]
     pub api_token: Option<String>,
-    #[clap(long, default_value = "http://localhost:8765")]
-    pub anki_endpoint: String,
+    #[clap(
+        long,
+        conflicts_with = "api_token",
+        help = "Read the WaniKani API token from stdin"
+    )]
+    pub api_token_stdin: bool,
+    #[clap(
+        long,
+        help = "Address of Anki Connect [default: http://localhost:8765]"
+    )]
+    pub anki_endpoint: Option<String>,
+    #[clap(
+        long,
+        value_enum,
+        default_value = "text",
+        global = true,
+        help = "Format of the command output"
+    )]
+    pub output: OutputFormat,
 }
 
 #[derive(clap::Subcommand)]
 pub enum Command {
+    #[clap(
+        about = "Write a configuration file, create the cache directory and check the connections"
+    )]
+    Init(InitOptions),
     #[clap(about = "Download all kanji data from wanikani")]
     QueryKanji,
     #[clap(about = "Download all vocabulary data from wanikani")]
@@ -37,68 +83,290 @@ pub enum Command {
     #[clap(about = "Create Anki deck and Anki card type for Vocabulary")]
     CreateVocabularyDeck,
     #[clap(about = "Install previously downloaded Kanji data into Anki deck")]
-    InstallKanji,
+    InstallKanji(InstallOptions),
     #[clap(about = "Install previously downloaded Vocabulary data into Anki deck")]
-    InstallVocabulary,
-    #[clap(about = "Update Anki model styling to use the included CSS file")]
+    InstallVocabulary(InstallOptions),
+    #[clap(about = "Update Anki model styling to use the configured CSS file")]
     UpdateModelStyling,
-    #[clap(about = "Update Anki model templates to use the included HTML files")]
-    UpdateModelTemplates,
+    #[clap(about = "Update Anki model templates to use the configured HTML files")]
+    UpdateModelTemplates(UpdateModelTemplatesOptions),
+    #[clap(about = "Add configured fields that are missing from existing Anki models")]
+    UpdateModelFields(UpdateModelFieldsOptions),
+    #[clap(about = "Show the state of the downloaded data and of the Anki decks")]
+    Status,
+    #[clap(about = "Check the configuration, Anki Connect and the WaniKani API token")]
+    Doctor,
+    #[clap(about = "Write the built-in card templates and CSS file out for customization")]
+    ExportResources(ExportResourcesOptions),
+    #[clap(about = "Inspect the configuration")]
+    Config(ConfigOptions),
 }
 
-#[derive(Debug, Deserialize)]
-pub struct Configuration {
-    pub kanji: ConfigurationDeckOptions,
-    pub vocabulary: ConfigurationDeckOptions,
+impl Command {
+    /// The name of the command as it is typed on the command line.
+    pub fn name(&self) -> &'static str {
+        match self {
+            Command::Init(_) => "init",
+            Command::QueryKanji => "query-kanji",
+            Command::QueryVocabulary => "query-vocabulary",
+            Command::CreateKanjiDeck => "create-kanji-deck",
+            Command::CreateVocabularyDeck => "create-vocabulary-deck",
+            Command::InstallKanji(_) => "install-kanji",
+            Command::InstallVocabulary(_) => "install-vocabulary",
+            Command::UpdateModelStyling => "update-model-styling",
+            Command::UpdateModelTemplates(_) => "update-model-templates",
+            Command::UpdateModelFields(_) => "update-model-fields",
+            Command::Status => "status",
+            Command::Doctor => "doctor",
+            Command::ExportResources(_) => "export-resources",
+            Command::Config(ConfigOptions {
+                command: ConfigCommand::Show,
+            }) => "config show",
+        }
+    }
+}
+
+#[derive(clap::Args)]
+pub struct InitOptions {
+    #[clap(
+        long,
+        help = "Do not ask for the settings, use the options and defaults instead"
+    )]
+    pub non_interactive: bool,
+    #[clap(long, help = "Replace an existing configuration file")]
+    pub force: bool,
+    #[clap(
+        long,
+        help = "Store the token given with --api-token or --api-token-stdin in the configuration file"
+    )]
+    pub store_token: bool,
+    #[clap(
+        long,
+        default_value = "Japanese Kanji",
+        help = "Name of the kanji deck and note type"
+    )]
+    pub kanji_deck: String,
+    #[clap(
+        long,
+        default_value = "Japanese Vocabulary",
+        help = "Name of the vocabulary deck and note type"
+    )]
+    pub vocabulary_deck: String,
+}
+
+#[derive(clap::Args)]
+pub struct InstallOptions {
+    #[clap(
+        long,
+        help = "Print what would be changed in Anki without changing anything"
+    )]
+    pub dry_run: bool,
+    #[clap(
+        long,
+        help = "Ignore the checkpoint of an interrupted install and start from the beginning"
+    )]
+    pub restart: bool,
+    #[clap(
+        long,
+        default_value_t = 4,
+        value_parser = clap::value_parser!(u16).range(1..),
+        help = "Maximum number of requests sent to Anki Connect at the same time"
+    )]
+    pub anki_concurrency: u16,
+    #[clap(
+        long = "level",
+        help = "Only install subjects of this level, can be repeated"
+    )]
+    pub levels: Vec<i64>,
+    #[clap(
+        long = "subject",
+        help = "Only install the subject with these characters, can be repeated"
+    )]
+    pub characters: Vec<String>,
+    #[clap(
+        long = "id",
+        help = "Only install the subject with this WaniKani id, can be repeated"
+    )]
+    pub ids: Vec<i64>,
+}
+
+#[derive(clap::Args)]
+pub struct UpdateModelTemplatesOptions {
+    #[clap(
+        long,
+        help = "Remove card templates that are not configured, deleting their cards"
+    )]
+    pub prune: bool,
+}
+
+#[derive(clap::Args)]
+pub struct UpdateModelFieldsOptions {
+    #[clap(
+        long,
+        help = "Remove fields that are not configured, deleting their contents"
+    )]
+    pub prune: bool,
 }
 
-#[derive(Debug, Deserialize)]
-pub struct ConfigurationDeckOptions {
-    pub deck_name: String,
-    pub model_name: String,
-    pub model_template_name: String,
-    pub model_template_front: String,
-    pub model_template_back: String,
+#[derive(clap::Args)]
+pub struct ExportResourcesOptions {
+    #[clap(
+        long,
+        default_value = "res",
+        help = "Directory to write the resources to"
+    )]
+    pub dir: String,
+    #[clap(long, help = "Replace files that already exist")]
+    pub force: bool,
+}
+
+#[derive(clap::Args)]
+pub struct ConfigOptions {
+    #[clap(subcommand)]
+    pub command: ConfigCommand,
+}
+
+#[derive(clap::Subcommand)]
+pub enum ConfigCommand {
+    #[clap(about = "Print the effective configuration and where each value comes from")]
+    Show,
+}
+
+#[derive(Clone, Copy, clap::ValueEnum)]
+pub enum OutputFormat {
+    Text,
+    Json,
 }
 
 #[tokio::main]
 async fn main() -> anyhow::Result<()> {
-    tracing_subscriber::fmt::init();
-
-    let settings = Config::builder()
-        .add_source(config::File::with_name("wanikanji"))
-        .build()?;
-    let configuration = settings
-        .try_deserialize::<Configuration>()
-        .expect("failed to deserialize configuration, does wanikanji.toml exist?");
-    tracing::debug!("running wanikanji with configuration {:?}", &configuration);
+    // Log to stderr, so that it does not get mixed up with the command output on stdout. Without RUST_LOG, the
+    // informational messages of wanikanji itself are shown.
+    tracing_subscriber::fmt()
+        .with_env_filter(
+            EnvFilter::try_from_default_env()
+                .unwrap_or_else(|_| EnvFilter::new("warn,wanikanji=info")),
+        )
+        .with_writer(std::io::stderr)
+        .init();
 
     let args = Options::parse();
-    let cache = FilesystemCache::new(&args.cache_dir).await?;
-    let wanikani_client = QueryClient::from_token(args.api_token.as_deref());
-    let anki_client = AnkiClient::from_endpoint(&args.anki_endpoint);
+    let started = Instant::now();
+    let mut summary = Summary::default();
+    let result = run(&args, &mut summary).await;
+    summary.elapsed_seconds = started.elapsed().as_secs_f64();
 
-    match args.command {
-        Command::QueryKanji => app::handle_query_kanji(&cache, &wanikani_client).await?,
-        Command::QueryVocabulary => app::handle_query_vocabulary(&cache, &wanikani_client).await?,
+    match args.output {
+        OutputFormat::Text => {
+            // The summary is printed even when the command failed, because a partial install still changed Anki.
+            if !summary.is_empty() {
+                println!("{}", summary);
+            }
+            result
+        }
+        OutputFormat::Json => {
+            let output = CommandOutput::new(args.command.name(), &summary, result.as_ref().err());
+            println!("{}", serde_json::to_string_pretty(&output)?);
+            if result.is_err() {
+                std::process::exit(1);
+            }
+            Ok(())
+        }
+    }
+}
+
+/// Load the configuration and run the requested command.
+async fn run(args: &Options, summary: &mut Summary) -> anyhow::Result<()> {
+    // These commands do not depend on the configuration or cache directory, so they have to run even when those are
+    // broken. The doctor checks them itself.
+    match &args.command {
+        Command::Init(options) => return app::handle_init(args, options, summary).await,
+        Command::Doctor => return app::handle_doctor(args, summary).await,
+        Command::ExportResources(options) => {
+            return app::handle_export_resources(options, summary).await
+        }
+        _ => {}
+    }
+    let layers = ConfigurationLayers::discover(args);
+    if let Command::Config(ConfigOptions {
+        command: ConfigCommand::Show,
+    }) = &args.command
+    {
+        return app::handle_config_show(&layers, summary);
+    }
+    let configuration = layers.load()?;
+    if layers.profile().is_some() {
+        tokio::fs::create_dir_all(&configuration.cache_dir).await?;
+    }
+    match configuration.cache_backend {
+        CacheBackend::Json => {
+            let cache =
+                FilesystemCache::new(&configuration.cache_dir, configuration.cache_compression)
+                    .await?;
+            run_with_cache(args, &configuration, &cache, summary).await
+        }
+        CacheBackend::Sqlite => {
+            let cache = SqliteCache::new(&configuration.cache_dir).await?;
+            run_with_cache(args, &configuration, &cache, summary).await
+        }
+    }
+}
+
+/// Run a command that needs the configuration and the cache.
+async fn run_with_cache(
+    args: &Options,
+    configuration: &Configuration,
+    cache: &impl Cache,
+    summary: &mut Summary,
+) -> anyhow::Result<()> {
+    let wanikani_client = QueryClient::from_token(configuration.api_token.as_ref());
+    let anki_client = AnkiClient::from_endpoint(&configuration.anki_endpoint);
+
+    match &args.command {
+        Command::QueryKanji => app::handle_query_kanji(cache, &wanikani_client, summary).await,
+        Command::QueryVocabulary => {
+            app::handle_query_vocabulary(cache, &wanikani_client, summary).await
+        }
         Command::CreateKanjiDeck => {
-            app::handle_create_kanji_deck(&anki_client, &configuration).await?
+            app::handle_create_kanji_deck(&anki_client, configuration).await
         }
         Command::CreateVocabularyDeck => {
-            app::handle_create_vocabulary_deck(&anki_client, &configuration).await?
+            app::handle_create_vocabulary_deck(&anki_client, configuration).await
         }
-        Command::InstallKanji => {
-            app::handle_install_kanji(&cache, &anki_client, &configuration).await?
+        Command::InstallKanji(options) => {
+            app::handle_install_kanji(
+                cache,
+                &wanikani_client,
+                &anki_client,
+                configuration,
+                options,
+                summary,
+            )
+            .await
         }
-        Command::InstallVocabulary => {
-            app::handle_install_vocabulary(&cache, &anki_client, &configuration).await?
+        Command::InstallVocabulary(options) => {
+            app::handle_install_vocabulary(
+                cache,
+                &wanikani_client,
+                &anki_client,
+                configuration,
+                options,
+                summary,
+            )
+            .await
         }
         Command::UpdateModelStyling => {
-            app::handle_update_model_styling(&anki_client, &configuration).await?
+            app::handle_update_model_styling(&anki_client, configuration).await
+        }
+        Command::UpdateModelTemplates(options) => {
+            app::handle_update_model_templates(&anki_client, configuration, options).await
+        }
+        Command::UpdateModelFields(options) => {
+            app::handle_update_model_fields(&anki_client, configuration, options).await
         }
-        Command::UpdateModelTemplates => {
-            app::handle_update_model_templates(&anki_client, &configuration).await?
+        Command::Status => app::handle_status(cache, &anki_client, configuration, summary).await,
+        Command::Init(_) | Command::Doctor | Command::ExportResources(_) | Command::Config(_) => {
+            unreachable!("the command runs before loading the configuration")
         }
     }
-    Ok(())
 }
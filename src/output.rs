@@ -0,0 +1,80 @@
+use crate::anki_connect::client::AnkiError;
+use crate::app::CommandError;
+use crate::configuration::ConfigurationError;
+use crate::io::IoError;
+use crate::progress::Summary;
+use crate::query::QueryError;
+use crate::token::TokenError;
+use serde::Serialize;
+
+/// The single object a command prints when running with `--output json`.
+#[derive(Debug, Serialize)]
+pub struct CommandOutput<'a> {
+    pub command: &'a str,
+    pub ok: bool,
+    #[serde(flatten)]
+    pub summary: &'a Summary,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<ErrorOutput>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct ErrorOutput {
+    pub code: &'static str,
+    pub message: String,
+}
+
+impl<'a> CommandOutput<'a> {
+    pub fn new(command: &'a str, summary: &'a Summary, error: Option<&anyhow::Error>) -> Self {
+        Self {
+            command,
+            ok: error.is_none(),
+            summary,
+            error: error.map(|e| ErrorOutput {
+                code: error_code(e),
+                message: error_message(e),
+            }),
+        }
+    }
+}
+
+/// Join the messages of the error chain, skipping causes that an outer error already includes in its message.
+fn error_message(error: &anyhow::Error) -> String {
+    let mut message = String::new();
+    for cause in error.chain() {
+        let text = cause.to_string();
+        if message.contains(&text) {
+            continue;
+        }
+        if !message.is_empty() {
+            message.push_str(": ");
+        }
+        message.push_str(&text);
+    }
+    message
+}
+
+/// Find the code of the first error in the chain that has one, falling back to a generic code.
+fn error_code(error: &anyhow::Error) -> &'static str {
+    for cause in error.chain() {
+        if let Some(e) = cause.downcast_ref::<QueryError>() {
+            return e.code();
+        }
+        if let Some(e) = cause.downcast_ref::<AnkiError>() {
+            return e.code();
+        }
+        if let Some(e) = cause.downcast_ref::<IoError>() {
+            return e.code();
+        }
+        if let Some(e) = cause.downcast_ref::<TokenError>() {
+            return e.code();
+        }
+        if let Some(e) = cause.downcast_ref::<CommandError>() {
+            return e.code();
+        }
+        if cause.is::<config::ConfigError>() || cause.is::<ConfigurationError>() {
+            return "configuration-invalid";
+        }
+    }
+    "error"
+}
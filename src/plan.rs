@@ -0,0 +1,301 @@
+use crate::anki_connect::client::{AnkiClient, AnkiError};
+use crate::anki_connect::rpc::add_note::AddNoteInput;
+use crate::anki_connect::rpc::change_deck::ChangeDeckInput;
+use crate::anki_connect::rpc::find_notes::FindNotesInput;
+use crate::anki_connect::rpc::notes_info::{NotesInfoInput, NotesInfoNoteMessage};
+use crate::anki_connect::rpc::update_note_fields::{
+    UpdateNoteFieldsInput, UpdateNoteFieldsNoteMessage,
+};
+use serde::Serialize;
+use std::collections::{HashMap, HashSet};
+use std::fmt;
+
+/// The set of changes an install would make to a single deck.
+#[derive(Debug, Serialize)]
+pub struct InstallPlan {
+    pub deck_name: String,
+    pub model_name: String,
+    pub notes: Vec<PlannedNote>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct PlannedNote {
+    /// The value of the note's key field, which is used to match it against existing notes.
+    pub key: String,
+    #[serde(flatten)]
+    pub action: NoteAction,
+    #[serde(skip)]
+    pub input: AddNoteInput,
+    #[serde(skip)]
+    pub cards: Vec<i64>,
+}
+
+#[derive(Debug, Serialize)]
+#[serde(tag = "action", rename_all = "kebab-case")]
+pub enum NoteAction {
+    /// The note does not exist in Anki yet.
+    Create,
+    /// The note exists in the target deck, but some of its fields are outdated.
+    Update {
+        note_id: i64,
+        changes: Vec<FieldChange>,
+    },
+    /// The note exists, but its cards live in another deck. Outdated fields are updated as well.
+    Move {
+        note_id: i64,
+        changes: Vec<FieldChange>,
+    },
+    /// The note exists in the target deck and is up-to-date.
+    Unchanged { note_id: i64 },
+}
+
+#[derive(Debug, Serialize)]
+pub struct FieldChange {
+    pub field: String,
+    pub old: Option<String>,
+    pub new: String,
+}
+
+impl InstallPlan {
+    pub fn count(&self, f: impl Fn(&NoteAction) -> bool) -> usize {
+        self.notes.iter().filter(|n| f(&n.action)).count()
+    }
+}
+
+impl AnkiClient<'_> {
+    pub async fn find_notes(&self, query: String) -> Result<Vec<i64>, AnkiError> {
+        self.send(FindNotesInput { query }).await
+    }
+
+    pub async fn notes_info(
+        &self,
+        notes: Vec<i64>,
+    ) -> Result<Vec<NotesInfoNoteMessage>, AnkiError> {
+        if notes.is_empty() {
+            return Ok(vec![]);
+        }
+        self.send(NotesInfoInput { notes }).await
+    }
+
+    /// Compute what installing the given notes would do against the current state of Anki.
+    ///
+    /// Notes are matched against existing notes of the same model by the value of `key_field`.
+    #[tracing::instrument(skip(self, inputs), err)]
+    pub async fn plan_install(
+        &self,
+        model_name: &str,
+        deck_name: &str,
+        key_field: &str,
+        inputs: Vec<AddNoteInput>,
+    ) -> Result<InstallPlan, AnkiError> {
+        let model_notes = self
+            .find_notes(format!("\"note:{}\"", escape_search(model_name)))
+            .await?;
+        let deck_notes = self
+            .find_notes(format!(
+                "\"note:{}\" \"deck:{}\"",
+                escape_search(model_name),
+                escape_search(deck_name)
+            ))
+            .await?
+            .into_iter()
+            .collect::<HashSet<_>>();
+        let existing = self
+            .notes_info(model_notes)
+            .await?
+            .into_iter()
+            .filter_map(|note| {
+                let key = note.fields.get(key_field)?.value.clone();
+                Some((key, note))
+            })
+            .collect::<HashMap<_, _>>();
+        tracing::debug!(
+            "found {} existing notes for model {}",
+            existing.len(),
+            model_name
+        );
+
+        let notes = inputs
+            .into_iter()
+            .map(|input| {
+                let key = input
+                    .note
+                    .fields
+                    .get(key_field)
+                    .cloned()
+                    .unwrap_or_default();
+                let Some(note) = existing.get(&key) else {
+                    return PlannedNote {
+                        key,
+                        action: NoteAction::Create,
+                        input,
+                        cards: vec![],
+                    };
+                };
+                let mut changes = input
+                    .note
+                    .fields
+                    .iter()
+                    .filter_map(|(field, new)| {
+                        let old = note.fields.get(field).map(|f| f.value.clone());
+                        match old {
+                            Some(old) if &old == new => None,
+                            old => Some(FieldChange {
+                                field: field.clone(),
+                                old,
+                                new: new.clone(),
+                            }),
+                        }
+                    })
+                    .collect::<Vec<_>>();
+                changes.sort_by(|a, b| a.field.cmp(&b.field));
+                let action = if !deck_notes.contains(&note.note_id) {
+                    NoteAction::Move {
+                        note_id: note.note_id,
+                        changes,
+                    }
+                } else if !changes.is_empty() {
+                    NoteAction::Update {
+                        note_id: note.note_id,
+                        changes,
+                    }
+                } else {
+                    NoteAction::Unchanged {
+                        note_id: note.note_id,
+                    }
+                };
+                PlannedNote {
+                    key,
+                    action,
+                    input,
+                    cards: note.cards.clone(),
+                }
+            })
+            .collect();
+        Ok(InstallPlan {
+            deck_name: deck_name.to_owned(),
+            model_name: model_name.to_owned(),
+            notes,
+        })
+    }
+
+    /// Perform the action planned for a single note.
+    pub async fn apply_planned_note(
+        &self,
+        deck_name: &str,
+        note: &PlannedNote,
+    ) -> Result<(), AnkiError> {
+        match &note.action {
+            NoteAction::Create => match self.send(note.input.clone()).await {
+                Ok(_) => Ok(()),
+                Err(AnkiError::ApiError(err))
+                    if err.contains("cannot create note because it is a duplicate") =>
+                {
+                    Ok(())
+                }
+                Err(e) => Err(e),
+            },
+            NoteAction::Update { note_id, changes } => {
+                self.update_note_fields(*note_id, changes).await
+            }
+            NoteAction::Move { note_id, changes } => {
+                let request = ChangeDeckInput {
+                    cards: note.cards.clone(),
+                    deck: deck_name.to_owned(),
+                };
+                match self.send(request).await {
+                    Ok(_) | Err(AnkiError::EmptyResponse) => {}
+                    Err(e) => return Err(e),
+                }
+                if changes.is_empty() {
+                    return Ok(());
+                }
+                self.update_note_fields(*note_id, changes).await
+            }
+            NoteAction::Unchanged { .. } => Ok(()),
+        }
+    }
+
+    async fn update_note_fields(
+        &self,
+        note_id: i64,
+        changes: &[FieldChange],
+    ) -> Result<(), AnkiError> {
+        let request = UpdateNoteFieldsInput {
+            note: UpdateNoteFieldsNoteMessage {
+                id: note_id,
+                fields: changes
+                    .iter()
+                    .map(|c| (c.field.clone(), c.new.clone()))
+                    .collect(),
+            },
+        };
+        match self.send(request).await {
+            Ok(_) => Ok(()),
+            Err(AnkiError::EmptyResponse) => Ok(()),
+            Err(e) => Err(e),
+        }
+    }
+}
+
+/// Escape a value for use inside a quoted Anki search term.
+pub fn escape_search(value: &str) -> String {
+    let mut escaped = String::with_capacity(value.len());
+    for c in value.chars() {
+        if matches!(c, '"' | '*' | '_' | '\\') {
+            escaped.push('\\');
+        }
+        escaped.push(c);
+    }
+    escaped
+}
+
+/// Shorten a field value so it fits on a single line of the plan table.
+fn truncate(value: &str, width: usize) -> String {
+    let value = value.replace('\n', " ");
+    if value.chars().count() <= width {
+        return value;
+    }
+    let mut truncated = value.chars().take(width - 1).collect::<String>();
+    truncated.push('…');
+    truncated
+}
+
+impl fmt::Display for InstallPlan {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(
+            f,
+            "install plan for deck '{}' (model '{}')",
+            self.deck_name, self.model_name
+        )?;
+        writeln!(f, "{:<10} {:<12} KEY", "ACTION", "NOTE")?;
+        for note in &self.notes {
+            let (action, note_id, changes) = match &note.action {
+                NoteAction::Create => ("create", None, None),
+                NoteAction::Update { note_id, changes } => ("update", Some(note_id), Some(changes)),
+                NoteAction::Move { note_id, changes } => ("move", Some(note_id), Some(changes)),
+                NoteAction::Unchanged { note_id } => ("unchanged", Some(note_id), None),
+            };
+            let note_id = note_id.map(|id| id.to_string()).unwrap_or_default();
+            writeln!(f, "{:<10} {:<12} {}", action, note_id, note.key)?;
+            for change in changes.into_iter().flatten() {
+                writeln!(
+                    f,
+                    "{:<23} {}: {:?} -> {:?}",
+                    "",
+                    change.field,
+                    truncate(change.old.as_deref().unwrap_or_default(), 40),
+                    truncate(&change.new, 40)
+                )?;
+            }
+        }
+        write!(
+            f,
+            "{} to create, {} to update, {} to move, {} unchanged",
+            self.count(|a| matches!(a, NoteAction::Create)),
+            self.count(|a| matches!(a, NoteAction::Update { .. })),
+            self.count(|a| matches!(a, NoteAction::Move { .. })),
+            self.count(|a| matches!(a, NoteAction::Unchanged { .. })),
+        )
+    }
+}
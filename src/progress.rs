@@ -0,0 +1,197 @@
+use crate::configuration::ConfigurationReport;
+use crate::doctor::DoctorReport;
+use crate::init::InitReport;
+use crate::plan::{InstallPlan, NoteAction};
+use crate::status::StatusReport;
+use indicatif::{ProgressBar, ProgressStyle};
+use serde::Serialize;
+use std::fmt;
+use std::io::IsTerminal;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+/// How long to wait between progress log lines when stderr is not a terminal.
+const LOG_INTERVAL: Duration = Duration::from_secs(10);
+
+/// Reports the progress of a long running step.
+///
+/// On a terminal this draws a progress bar with an ETA. Otherwise, such as when the output is redirected to a file,
+/// the progress is logged periodically instead.
+pub struct Progress {
+    bar: ProgressBar,
+    label: String,
+    last_log: Option<Mutex<Instant>>,
+}
+
+impl Progress {
+    pub fn new(label: &str, unit: &str) -> Self {
+        if !std::io::stderr().is_terminal() {
+            return Self {
+                bar: ProgressBar::hidden(),
+                label: label.to_owned(),
+                last_log: Some(Mutex::new(Instant::now())),
+            };
+        }
+        let template = format!(
+            "{{msg}} [{{bar:40}}] {{pos}}/{{len}} {} ({{eta}} remaining)",
+            unit
+        );
+        // SAFETY: The template is a constant apart from the unit, which does not contain placeholders.
+        let style = ProgressStyle::with_template(&template)
+            .expect("failed to parse progress bar template")
+            .progress_chars("=> ");
+        let bar = ProgressBar::new(0)
+            .with_style(style)
+            .with_message(label.to_owned());
+        Self {
+            bar,
+            label: label.to_owned(),
+            last_log: None,
+        }
+    }
+
+    /// Set the total amount of work, once it is known.
+    pub fn set_length(&self, length: u64) {
+        self.bar.set_length(length);
+    }
+
+    pub fn inc(&self, delta: u64) {
+        self.bar.inc(delta);
+        let Some(last_log) = &self.last_log else {
+            return;
+        };
+        // SAFETY: The lock is only held while updating the timestamp, so it cannot be poisoned by a panic elsewhere.
+        let mut last_log = last_log.lock().expect("progress log lock was poisoned");
+        if last_log.elapsed() >= LOG_INTERVAL {
+            *last_log = Instant::now();
+            tracing::info!(
+                "{}: {}/{}",
+                self.label,
+                self.bar.position(),
+                self.bar.length().unwrap_or_default()
+            );
+        }
+    }
+
+    pub fn position(&self) -> u64 {
+        self.bar.position()
+    }
+
+    pub fn finish(&self) {
+        self.bar.finish_and_clear();
+    }
+}
+
+/// The outcome of a command, printed once the command completes.
+#[derive(Debug, Default, Serialize)]
+pub struct Summary {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub pages_fetched: Option<usize>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub subjects_fetched: Option<usize>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub notes: Option<NoteCounts>,
+    /// The plan of a dry-run install.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub plan: Option<InstallPlan>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub status: Option<StatusReport>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub doctor: Option<DoctorReport>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub configuration: Option<ConfigurationReport>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub init: Option<InitReport>,
+    /// The subjects that failed to install, along with the reason.
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub failures: Vec<String>,
+    /// The files written by `export-resources`.
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub exported: Vec<String>,
+    pub elapsed_seconds: f64,
+}
+
+impl Summary {
+    /// Whether there is anything worth reporting beyond the elapsed time.
+    pub fn is_empty(&self) -> bool {
+        self.pages_fetched.is_none()
+            && self.notes.is_none()
+            && self.plan.is_none()
+            && self.status.is_none()
+            && self.doctor.is_none()
+            && self.configuration.is_none()
+            && self.init.is_none()
+            && self.exported.is_empty()
+    }
+}
+
+/// How many notes an install created, updated, moved, skipped or failed to install.
+///
+/// Notes that were unchanged, or already installed according to the install checkpoint, count as skipped. Notes are
+/// counted as they are applied, so an install that stopped early only counts what it did.
+#[derive(Debug, Default, Serialize)]
+pub struct NoteCounts {
+    pub created: usize,
+    pub updated: usize,
+    pub moved: usize,
+    pub skipped: usize,
+    pub failed: usize,
+}
+
+impl NoteCounts {
+    /// Count a note that was applied as planned.
+    pub fn record(&mut self, action: &NoteAction) {
+        let count = match action {
+            NoteAction::Create => &mut self.created,
+            NoteAction::Update { .. } => &mut self.updated,
+            NoteAction::Move { .. } => &mut self.moved,
+            NoteAction::Unchanged { .. } => &mut self.skipped,
+        };
+        *count += 1;
+    }
+}
+
+impl fmt::Display for Summary {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if let Some(configuration) = &self.configuration {
+            return write!(f, "{}", configuration.to_string().trim_end());
+        }
+        if let Some(init) = &self.init {
+            return write!(f, "{}", init.to_string().trim_end());
+        }
+        if let Some(doctor) = &self.doctor {
+            return write!(f, "{}", doctor.to_string().trim_end());
+        }
+        if let Some(status) = &self.status {
+            return write!(f, "{}", status.to_string().trim_end());
+        }
+        if let Some(plan) = &self.plan {
+            writeln!(f, "{}", plan)?;
+        }
+        let mut parts = Vec::new();
+        if let Some(pages) = self.pages_fetched {
+            parts.push(format!("fetched {} pages", pages));
+        }
+        if let Some(subjects) = self.subjects_fetched {
+            parts.push(format!("{} subjects", subjects));
+        }
+        if let Some(notes) = &self.notes {
+            parts.push(format!(
+                "{} notes created, {} updated, {} moved, {} skipped, {} failed",
+                notes.created, notes.updated, notes.moved, notes.skipped, notes.failed
+            ));
+        }
+        if !self.exported.is_empty() {
+            parts.push(format!("wrote {}", self.exported.join(", ")));
+        }
+        if parts.is_empty() {
+            let done = if self.plan.is_some() {
+                "planned"
+            } else {
+                "done"
+            };
+            parts.push(done.to_owned());
+        }
+        write!(f, "{} in {:.1}s", parts.join(", "), self.elapsed_seconds)
+    }
+}
@@ -1,9 +1,11 @@
+use crate::token::ApiToken;
 use futures::future::BoxFuture;
 use futures::FutureExt;
-use reqwest::header::HeaderMap;
+use reqwest::header::{HeaderMap, HeaderValue};
 use reqwest::StatusCode;
 use serde::de::DeserializeOwned;
 use serde::{Deserialize, Serialize};
+use std::fmt;
 use thiserror::Error;
 
 #[derive(Debug, Error)]
@@ -14,13 +16,23 @@ pub enum QueryError {
     QueryFailed(StatusCode),
 }
 
-#[derive(Debug)]
+impl QueryError {
+    /// A stable identifier of the kind of error, for scripts that consume the JSON output.
+    pub fn code(&self) -> &'static str {
+        match self {
+            QueryError::HttpError(_) => "query-http-error",
+            QueryError::QueryFailed(StatusCode::UNAUTHORIZED) => "query-unauthorized",
+            QueryError::QueryFailed(_) => "query-failed",
+        }
+    }
+}
+
 pub struct QueryClient {
     client: reqwest::Client,
 }
 
 impl QueryClient {
-    pub fn from_token(token: Option<&str>) -> Self {
+    pub fn from_token(token: Option<&ApiToken>) -> Self {
         let client = reqwest::Client::builder()
             .default_headers({
                 let mut headers = HeaderMap::new();
@@ -31,12 +43,12 @@ impl QueryClient {
                         .expect("failed to parse string as header value"),
                 );
                 if let Some(token) = token {
-                    headers.insert(
-                        "Authorization",
-                        format!("Bearer {}", token)
-                            .parse()
-                            .expect("failed to interpolate token into header value"),
-                    );
+                    let mut value: HeaderValue = format!("Bearer {}", token.expose())
+                        .parse()
+                        .expect("failed to interpolate token into header value");
+                    // Keeps the header out of the debug output of reqwest.
+                    value.set_sensitive(true);
+                    headers.insert("Authorization", value);
                 };
                 headers
             })
@@ -70,8 +82,17 @@ impl QueryClient {
     }
 }
 
+impl fmt::Debug for QueryClient {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        // The client carries the API token in its default headers, so it is left out.
+        f.debug_struct("QueryClient").finish_non_exhaustive()
+    }
+}
+
 #[derive(Debug, Deserialize, Serialize)]
 pub struct ApiObjectMessage<T> {
+    /// The identifier of the resource, which is only present for resources that are stored in the database.
+    pub id: Option<i64>,
     pub object: String,
     pub url: String,
     pub data_updated_at: Option<String>,
@@ -88,6 +109,14 @@ pub struct ApiCollectionMessage<T> {
     pub pages: ApiPaginationMessage,
 }
 
+impl<T> ApiCollectionMessage<T> {
+    /// The number of pages it takes to list the whole collection.
+    pub fn page_count(&self) -> u64 {
+        let per_page = self.pages.per_page.max(1) as u64;
+        (self.total_count.max(0) as u64).div_ceil(per_page)
+    }
+}
+
 #[derive(Debug, Deserialize, Serialize)]
 pub struct ApiPaginationMessage {
     pub per_page: i32,
@@ -95,8 +124,16 @@ pub struct ApiPaginationMessage {
     pub previous_url: Option<String>,
 }
 
-#[derive(Debug, Deserialize, Serialize)]
+#[derive(Debug, Default, Deserialize, Serialize)]
 pub struct ApiSubjectMessage {
+    /// The identifier of the subject. This is not part of the subject data returned by the API, but is copied over
+    /// from the enclosing resource object.
+    #[serde(default)]
+    pub id: i64,
+    /// The last time the subject was changed on WaniKani, which is copied over from the enclosing resource object like
+    /// the identifier.
+    #[serde(default)]
+    pub data_updated_at: Option<String>,
     pub auxiliary_meanings: Vec<ApiAuxiliaryMeaningMessage>,
     /// The character(s) that make up the subject's item. This value can be null only if the subject
     /// is a radical item.
@@ -112,6 +149,25 @@ pub struct ApiSubjectMessage {
     pub spaced_repetition_system_id: i32,
 }
 
+impl ApiSubjectMessage {
+    /// The properties that every subject has, which field templates can use as variables.
+    pub const PROPERTIES: [&'static str; 13] = [
+        "id",
+        "data_updated_at",
+        "auxiliary_meanings",
+        "characters",
+        "created_at",
+        "document_url",
+        "hidden_at",
+        "lesson_position",
+        "level",
+        "meaning_mnemonic",
+        "meanings",
+        "slug",
+        "spaced_repetition_system_id",
+    ];
+}
+
 #[derive(Debug, Deserialize, Serialize)]
 pub struct ApiMeaningMessage {
     pub meaning: String,
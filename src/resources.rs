@@ -0,0 +1,118 @@
+use serde::Deserialize;
+use std::fmt;
+use std::path::{Path, PathBuf};
+
+/// The card templates and stylesheet that are built into the binary, by their path in the repository.
+pub const BUILTIN_RESOURCES: [(&str, &str); 5] = [
+    ("res/anki.css", include_str!("../res/anki.css")),
+    (
+        "res/kanji-card-front.html",
+        include_str!("../res/kanji-card-front.html"),
+    ),
+    (
+        "res/kanji-card-back.html",
+        include_str!("../res/kanji-card-back.html"),
+    ),
+    (
+        "res/vocabulary-card-front.html",
+        include_str!("../res/vocabulary-card-front.html"),
+    ),
+    (
+        "res/vocabulary-card-back.html",
+        include_str!("../res/vocabulary-card-back.html"),
+    ),
+];
+
+/// A card template or stylesheet, which is either built into the binary or read from a file.
+///
+/// Resources that are configured in the configuration file are always files, whose relative paths are resolved
+/// against the directory of the configuration file.
+#[derive(Debug, Clone)]
+pub enum Resource {
+    Builtin(&'static str),
+    File(PathBuf),
+    /// A resource that is generated from the configuration, such as a built-in template adapted to it.
+    Generated(String),
+}
+
+impl Resource {
+    /// Refer to one of the built-in resources by its path in the repository.
+    pub fn builtin(path: &'static str) -> Self {
+        debug_assert!(BUILTIN_RESOURCES.iter().any(|(p, _)| *p == path));
+        Resource::Builtin(path)
+    }
+
+    /// Make a relative file path relative to the given directory instead of the working directory.
+    pub fn resolve(&mut self, base: &Path) {
+        if let Resource::File(path) = self {
+            if path.is_relative() {
+                *path = base.join(&*path);
+            }
+        }
+    }
+
+    pub async fn read(&self) -> Result<String, std::io::Error> {
+        match self {
+            Resource::Builtin(path) => {
+                let contents = builtin_contents(path)
+                    // SAFETY: Built-in resources are only constructed through `Resource::builtin`, which is called
+                    // with the constant paths above.
+                    .expect("unknown built-in resource");
+                Ok(contents.to_owned())
+            }
+            Resource::File(path) => tokio::fs::read_to_string(path).await,
+            Resource::Generated(contents) => Ok(contents.clone()),
+        }
+    }
+}
+
+/// The contents of the built-in resource with the given path in the repository.
+pub fn builtin_contents(path: &str) -> Option<&'static str> {
+    BUILTIN_RESOURCES
+        .iter()
+        .find(|(p, _)| *p == path)
+        .map(|(_, contents)| *contents)
+}
+
+impl<'de> Deserialize<'de> for Resource {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: serde::Deserializer<'de>,
+    {
+        PathBuf::deserialize(deserializer).map(Resource::File)
+    }
+}
+
+impl fmt::Display for Resource {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Resource::Builtin(path) => write!(f, "built-in {}", path),
+            Resource::File(path) => write!(f, "{}", path.display()),
+            Resource::Generated(_) => f.write_str("generated"),
+        }
+    }
+}
+
+/// Write the built-in resources into a directory, so that they can be customized. Existing files are only replaced
+/// when `force` is set.
+pub async fn export_resources(dir: &Path, force: bool) -> Result<Vec<PathBuf>, std::io::Error> {
+    tokio::fs::create_dir_all(dir).await?;
+    let mut written = Vec::new();
+    for (path, contents) in BUILTIN_RESOURCES {
+        // SAFETY: Every built-in resource path has a file name.
+        let name = Path::new(path)
+            .file_name()
+            .expect("resource without file name");
+        let target = dir.join(name);
+        if !force && tokio::fs::try_exists(&target).await? {
+            tracing::warn!(
+                "not replacing existing file {}, pass --force to overwrite it",
+                target.display()
+            );
+            continue;
+        }
+        tokio::fs::write(&target, contents).await?;
+        written.push(target);
+    }
+    Ok(written)
+}
@@ -0,0 +1,169 @@
+use crate::anki_connect::client::{AnkiClient, AnkiError};
+use crate::anki_connect::rpc::deck_names::DeckNamesInput;
+use crate::anki_connect::rpc::model_names::ModelNamesInput;
+use crate::configuration::ConfigurationDeckOptions;
+use crate::io::{Cache, IoError};
+use crate::query::ApiSubjectMessage;
+use serde::Serialize;
+use std::fmt;
+use std::time::SystemTime;
+
+/// The state of the local cache and of the configured decks in Anki.
+#[derive(Debug, Serialize)]
+pub struct StatusReport {
+    pub cache_dir: String,
+    pub cache: Vec<CacheStatus>,
+    pub decks: Vec<DeckStatus>,
+}
+
+/// The state of a single kind of subject in the cache.
+#[derive(Debug, Serialize)]
+pub struct CacheStatus {
+    pub key: String,
+    pub subjects: Option<usize>,
+    /// The most recent time any of the cached subjects was changed on WaniKani.
+    pub data_updated_at: Option<String>,
+    /// How long ago the subjects were downloaded.
+    pub age_seconds: Option<u64>,
+    /// Whether the subjects were cached in a format this version of wanikanji cannot read.
+    pub outdated: bool,
+}
+
+/// The state of a configured deck in Anki.
+#[derive(Debug, Serialize)]
+pub struct DeckStatus {
+    pub deck_name: String,
+    pub model_name: String,
+    /// Whether Anki Connect answered. Nothing else is known about the deck when it did not.
+    pub anki_reachable: bool,
+    pub deck_exists: Option<bool>,
+    pub model_exists: Option<bool>,
+    pub notes: Option<usize>,
+    /// The number of cached subjects that do not have a note in the deck yet.
+    pub not_installed: Option<usize>,
+}
+
+impl CacheStatus {
+    /// Describe the subjects stored under the given cache key, which may not have been downloaded yet.
+    ///
+    /// `subjects` are the subjects read from the cache, or `None` when they were cached in an outdated format.
+    pub async fn load<'a, T>(
+        cache: &impl Cache,
+        key: &str,
+        subjects: Option<&'a [T]>,
+        subject: impl Fn(&'a T) -> &'a ApiSubjectMessage,
+    ) -> Result<Self, IoError> {
+        let modified = match cache.modified(key).await {
+            Ok(modified) => Some(modified),
+            Err(IoError::CacheItemNotFound) => None,
+            Err(e) => return Err(e),
+        };
+        let outdated = subjects.is_none();
+        let subjects = subjects.unwrap_or_default();
+        let age_seconds = modified.map(|modified| {
+            SystemTime::now()
+                .duration_since(modified)
+                .unwrap_or_default()
+                .as_secs()
+        });
+        // WaniKani timestamps are ISO 8601 in UTC, so the latest one also sorts last.
+        let data_updated_at = subjects
+            .iter()
+            .filter_map(|s| subject(s).data_updated_at.clone())
+            .max();
+        Ok(Self {
+            key: key.to_owned(),
+            subjects: modified.filter(|_| !outdated).map(|_| subjects.len()),
+            data_updated_at,
+            age_seconds,
+            outdated,
+        })
+    }
+}
+
+impl DeckStatus {
+    /// The status of a deck when Anki Connect could not be reached.
+    pub fn unreachable(deck: &ConfigurationDeckOptions) -> Self {
+        Self {
+            deck_name: deck.deck_name.clone(),
+            model_name: deck.model_name.clone(),
+            anki_reachable: false,
+            deck_exists: None,
+            model_exists: None,
+            notes: None,
+            not_installed: None,
+        }
+    }
+}
+
+impl AnkiClient<'_> {
+    pub async fn model_names(&self) -> Result<Vec<String>, AnkiError> {
+        self.send(ModelNamesInput {}).await
+    }
+
+    pub async fn deck_names(&self) -> Result<Vec<String>, AnkiError> {
+        self.send(DeckNamesInput {}).await
+    }
+}
+
+impl fmt::Display for StatusReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "Cache ({})", self.cache_dir)?;
+        for cache in &self.cache {
+            if cache.outdated {
+                writeln!(
+                    f,
+                    "  {:<12} outdated, run query-{} to download it again",
+                    cache.key, cache.key
+                )?;
+                continue;
+            }
+            let (Some(subjects), Some(age)) = (cache.subjects, cache.age_seconds) else {
+                writeln!(f, "  {:<12} not downloaded", cache.key)?;
+                continue;
+            };
+            write!(
+                f,
+                "  {:<12} {} subjects, downloaded {} ago",
+                cache.key,
+                subjects,
+                format_age(age)
+            )?;
+            match &cache.data_updated_at {
+                Some(updated) => writeln!(f, ", last changed on WaniKani at {}", updated)?,
+                None => writeln!(f)?,
+            }
+        }
+        writeln!(f, "Anki")?;
+        for deck in &self.decks {
+            if !deck.anki_reachable {
+                writeln!(f, "  {}: Anki unreachable", deck.deck_name)?;
+                continue;
+            }
+            let mut parts = Vec::new();
+            if deck.model_exists == Some(false) {
+                parts.push(format!("note type '{}' does not exist", deck.model_name));
+            }
+            if deck.deck_exists == Some(false) {
+                parts.push("deck does not exist".to_owned());
+            }
+            if let Some(notes) = deck.notes {
+                parts.push(format!("{} notes", notes));
+            }
+            if let Some(not_installed) = deck.not_installed {
+                parts.push(format!("{} cached subjects not installed", not_installed));
+            }
+            writeln!(f, "  {}: {}", deck.deck_name, parts.join(", "))?;
+        }
+        Ok(())
+    }
+}
+
+fn format_age(seconds: u64) -> String {
+    match seconds {
+        0..=59 => format!("{}s", seconds),
+        60..=3599 => format!("{}m", seconds / 60),
+        3600..=86399 => format!("{}h", seconds / 3600),
+        _ => format!("{}d", seconds / 86400),
+    }
+}
@@ -0,0 +1,158 @@
+use crate::furigana::FuriganaDictionary;
+use minijinja::value::Rest;
+use minijinja::{Environment, UndefinedBehavior};
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use std::sync::Arc;
+use thiserror::Error;
+
+#[derive(Debug, Error)]
+pub enum TemplateError {
+    #[error("invalid template '{0}': {1}")]
+    Syntax(String, minijinja::Error),
+    #[error("failed to render template: {0}")]
+    Render(minijinja::Error),
+    #[error("template '{0}' uses unknown variables: {}", .1.join(", "))]
+    UnknownVariables(String, Vec<String>),
+}
+
+/// A template that renders a note field from a WaniKani subject.
+///
+/// Templates use the Jinja syntax, and every property of the subject is available as a variable. Using a variable that
+/// does not exist is an error rather than an empty value. On top of the built-in filters, the following helpers are
+/// available:
+///
+/// - `join(values, separator)` joins a list of values, defaulting to `, ` as the separator
+/// - `furigana(text, reading)` produces Anki furigana markup such as `漢字[かんじ]`
+/// - `markup_to_html(text)` converts WaniKani mnemonic markup like `<kanji>` into HTML spans
+/// - `reading_list(readings, type)` lists the kanji readings of a type, marking readings that are not accepted answers
+#[derive(Clone, Deserialize, Serialize)]
+#[serde(try_from = "String", into = "String")]
+pub struct FieldTemplate {
+    source: String,
+    environment: Arc<Environment<'static>>,
+}
+
+/// The name of the single template registered in each field template environment.
+const TEMPLATE_NAME: &str = "field";
+
+impl FieldTemplate {
+    pub fn parse(source: &str) -> Result<Self, TemplateError> {
+        let mut environment = Environment::new();
+        environment.set_undefined_behavior(UndefinedBehavior::Strict);
+        environment.add_function("join", join);
+        environment.add_function("furigana", furigana);
+        environment.add_function("markup_to_html", markup_to_html);
+        environment.add_function("reading_list", reading_list);
+        environment.add_filter("furigana", furigana);
+        environment.add_filter("markup_to_html", markup_to_html);
+        environment
+            .add_template_owned(TEMPLATE_NAME, source.to_owned())
+            .map_err(|e| TemplateError::Syntax(source.to_owned(), e))?;
+        Ok(Self {
+            source: source.to_owned(),
+            environment: Arc::new(environment),
+        })
+    }
+
+    /// Check that the template only uses the given variables, besides the helpers and built-in functions.
+    pub fn check_variables(&self, known: &[&str]) -> Result<(), TemplateError> {
+        let template = self
+            .environment
+            .get_template(TEMPLATE_NAME)
+            .map_err(TemplateError::Render)?;
+        let globals = self
+            .environment
+            .globals()
+            .map(|(name, _)| name)
+            .collect::<Vec<_>>();
+        let mut unknown = template
+            .undeclared_variables(false)
+            .into_iter()
+            .filter(|name| !known.contains(&name.as_str()) && !globals.contains(&name.as_str()))
+            .collect::<Vec<_>>();
+        if unknown.is_empty() {
+            return Ok(());
+        }
+        unknown.sort();
+        Err(TemplateError::UnknownVariables(
+            self.source.clone(),
+            unknown,
+        ))
+    }
+
+    /// Render the template with the properties of the given subject as its context.
+    pub fn render(&self, subject: &serde_json::Value) -> Result<String, TemplateError> {
+        self.environment
+            .get_template(TEMPLATE_NAME)
+            .and_then(|t| t.render(subject))
+            .map_err(TemplateError::Render)
+    }
+}
+
+fn join(values: Vec<minijinja::Value>, separator: Rest<String>) -> String {
+    let separator = separator.first().map_or(", ", String::as_str);
+    values
+        .iter()
+        .map(|v| v.to_string())
+        .collect::<Vec<_>>()
+        .join(separator)
+}
+
+fn furigana(text: String, reading: String) -> String {
+    FuriganaDictionary::default().annotate_word(&text, &reading)
+}
+
+fn reading_list(
+    readings: Vec<minijinja::Value>,
+    r#type: String,
+) -> Result<String, minijinja::Error> {
+    let mut list = Vec::new();
+    for reading in readings {
+        if reading.get_attr("type")?.as_str() != Some(r#type.as_str()) {
+            continue;
+        }
+        let text = reading.get_attr("reading")?.to_string();
+        if reading.get_attr("accepted_answer")?.is_true() {
+            list.push(text);
+        } else {
+            list.push(format!(
+                "<span class=\"reading--unaccepted\">{}</span>",
+                text
+            ));
+        }
+    }
+    Ok(list.join(", "))
+}
+
+/// Convert the tags WaniKani uses to highlight parts of mnemonics into HTML spans.
+pub fn markup_to_html(text: String) -> String {
+    const TAGS: [&str; 6] = ["radical", "kanji", "vocabulary", "reading", "meaning", "ja"];
+    TAGS.iter().fold(text, |text, tag| {
+        text.replace(
+            &format!("<{}>", tag),
+            &format!("<span class=\"wk-{}\">", tag),
+        )
+        .replace(&format!("</{}>", tag), "</span>")
+    })
+}
+
+impl TryFrom<String> for FieldTemplate {
+    type Error = TemplateError;
+
+    fn try_from(value: String) -> Result<Self, Self::Error> {
+        Self::parse(&value)
+    }
+}
+
+impl From<FieldTemplate> for String {
+    fn from(value: FieldTemplate) -> Self {
+        value.source
+    }
+}
+
+impl fmt::Debug for FieldTemplate {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_tuple("FieldTemplate").field(&self.source).finish()
+    }
+}
@@ -0,0 +1,97 @@
+use serde::Deserialize;
+use std::fmt;
+use std::io::Read;
+use std::path::{Path, PathBuf};
+use thiserror::Error;
+
+#[derive(Debug, Error)]
+pub enum TokenError {
+    #[error("failed to read API token from {path}: {error}")]
+    FileUnreadable {
+        path: PathBuf,
+        error: std::io::Error,
+    },
+    #[error("failed to read API token from stdin: {0}")]
+    StdinUnreadable(std::io::Error),
+    #[error("API token from {0} is empty")]
+    Empty(String),
+}
+
+impl TokenError {
+    /// A stable identifier of the kind of error, for scripts that consume the JSON output.
+    pub fn code(&self) -> &'static str {
+        match self {
+            TokenError::FileUnreadable { .. } => "token-file-unreadable",
+            TokenError::StdinUnreadable(_) => "token-stdin-unreadable",
+            TokenError::Empty(_) => "token-empty",
+        }
+    }
+}
+
+/// A WaniKani API token, which never shows up in logs or debug output.
+#[derive(Clone, Deserialize)]
+#[serde(transparent)]
+pub struct ApiToken(String);
+
+impl ApiToken {
+    pub fn new(token: &str) -> Self {
+        Self(token.trim().to_owned())
+    }
+
+    /// The token itself, for sending it to WaniKani.
+    pub fn expose(&self) -> &str {
+        &self.0
+    }
+
+    /// Read the token from a file that contains nothing else, warning when other users can read the file.
+    pub fn from_file(path: &Path) -> Result<Self, TokenError> {
+        let contents =
+            std::fs::read_to_string(path).map_err(|error| TokenError::FileUnreadable {
+                path: path.to_owned(),
+                error,
+            })?;
+        warn_if_readable_by_others(path);
+        let token = Self::new(&contents);
+        if token.0.is_empty() {
+            return Err(TokenError::Empty(path.display().to_string()));
+        }
+        Ok(token)
+    }
+
+    /// Read the token from stdin, so that it does not show up in the shell history or in process listings.
+    pub fn from_stdin() -> Result<Self, TokenError> {
+        let mut contents = String::new();
+        std::io::stdin()
+            .read_to_string(&mut contents)
+            .map_err(TokenError::StdinUnreadable)?;
+        let token = Self::new(&contents);
+        if token.0.is_empty() {
+            return Err(TokenError::Empty("stdin".to_owned()));
+        }
+        Ok(token)
+    }
+}
+
+impl fmt::Debug for ApiToken {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("ApiToken(<redacted>)")
+    }
+}
+
+#[cfg(unix)]
+fn warn_if_readable_by_others(path: &Path) {
+    use std::os::unix::fs::PermissionsExt;
+    let Ok(metadata) = std::fs::metadata(path) else {
+        return;
+    };
+    if metadata.permissions().mode() & 0o044 != 0 {
+        tracing::warn!(
+            "API token file {} can be read by other users, restrict it with `chmod 600 {}`",
+            path.display(),
+            path.display()
+        );
+    }
+}
+
+#[cfg(not(unix))]
+fn warn_if_readable_by_others(_path: &Path) {}
@@ -0,0 +1,18 @@
+use crate::query::{ApiObjectMessage, QueryClient, QueryError};
+use serde::{Deserialize, Serialize};
+
+impl QueryClient {
+    #[tracing::instrument(skip(self), err)]
+    pub async fn get_user(&self) -> Result<ApiUserMessage, QueryError> {
+        let response = self
+            .get::<ApiObjectMessage<ApiUserMessage>>("https://api.wanikani.com/v2/user")
+            .await?;
+        Ok(response.data)
+    }
+}
+
+#[derive(Debug, Deserialize, Serialize)]
+pub struct ApiUserMessage {
+    pub username: String,
+    pub level: i32,
+}
@@ -1,12 +1,19 @@
+use crate::progress::Progress;
 use crate::query::{
     ApiCollectionMessage, ApiObjectMessage, ApiSubjectMessage, QueryClient, QueryError,
 };
 use serde::{Deserialize, Serialize};
 
+/// The WaniKani endpoint that lists every vocabulary subject.
+pub const VOCABULARY_URL: &str = "https://api.wanikani.com/v2/subjects?types=vocabulary";
+
 impl QueryClient {
-    #[tracing::instrument(skip(self), err)]
-    pub async fn list_vocabulary(&self) -> Result<Vec<ApiVocabularyMessage>, QueryError> {
-        let mut next_url = Some("https://api.wanikani.com/v2/subjects?types=vocabulary".to_owned());
+    #[tracing::instrument(skip(self, progress), err)]
+    pub async fn list_vocabulary(
+        &self,
+        progress: &Progress,
+    ) -> Result<Vec<ApiVocabularyMessage>, QueryError> {
+        let mut next_url = Some(VOCABULARY_URL.to_owned());
         let mut vocabulary = Vec::new();
 
         while let Some(url) = next_url {
@@ -14,15 +21,22 @@ impl QueryClient {
             let response = self
                 .get::<ApiCollectionMessage<ApiObjectMessage<ApiVocabularyMessage>>>(&url)
                 .await?;
-            let items = response.data.into_iter().map(|o| o.data);
+            progress.set_length(response.page_count());
+            let items = response.data.into_iter().map(|o| {
+                let mut item = o.data;
+                item.subject.id = o.id.unwrap_or_default();
+                item.subject.data_updated_at = o.data_updated_at;
+                item
+            });
             vocabulary.extend(items);
+            progress.inc(1);
             next_url = response.pages.next_url;
         }
         Ok(vocabulary)
     }
 }
 
-#[derive(Debug, Deserialize, Serialize)]
+#[derive(Debug, Default, Deserialize, Serialize)]
 pub struct ApiVocabularyMessage {
     #[serde(flatten)]
     pub subject: ApiSubjectMessage,
@@ -34,6 +48,20 @@ pub struct ApiVocabularyMessage {
     pub reading_mnemonic: String,
 }
 
+impl ApiVocabularyMessage {
+    /// The properties of a vocabulary on top of those that every subject has, including the furigana that is added
+    /// when building its note.
+    pub const PROPERTIES: [&'static str; 7] = [
+        "component_subject_ids",
+        "context_sentences",
+        "parts_of_speech",
+        "pronunciation_audios",
+        "readings",
+        "reading_mnemonic",
+        "with_furigana",
+    ];
+}
+
 #[derive(Debug, Deserialize, Serialize)]
 pub struct ApiVocabularyContextSentenceMessage {
     pub en: String,